@@ -0,0 +1,6 @@
+// Only compiled, to check that the generated header is valid C++.
+#include "ffi_slots.h"
+
+bool below_soft_limit() {
+    return ffi_requests_slot()->load() < ffi_limits.soft.load();
+}
@@ -0,0 +1,17 @@
+#include <stdio.h>
+
+#include "ffi_slots.h"
+
+bool ffi_request(void);
+
+int main(void) {
+    atomic_store(&ffi_limits.soft, 2);
+
+    int allowed = 0;
+    for (int i = 0; i < 5; i++) {
+        allowed += ffi_request();
+    }
+
+    printf("%d %llu\n", allowed, (unsigned long long)atomic_load(ffi_requests_slot()));
+    return 0;
+}
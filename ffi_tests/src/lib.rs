@@ -0,0 +1,33 @@
+//! A static library with exported slots, linked into the C program in `c` by the tests.
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed};
+
+use inline_cache::{c_struct, inline_cache};
+
+c_struct! {
+    pub struct Limits {
+        pub soft: AtomicU32,
+        pub hard: AtomicU32,
+    }
+}
+
+fn requests() -> &'static AtomicU64 {
+    inline_cache!(AtomicU64, export = "ffi_requests")
+}
+
+fn limits() -> &'static Limits {
+    inline_cache!(Limits, export = "ffi_limits")
+}
+
+/// Counts a request, returning whether it is below the soft limit set from C.
+#[unsafe(no_mangle)]
+pub extern "C" fn ffi_request() -> bool {
+    requests().fetch_add(1, Relaxed) < limits().soft.load(Relaxed) as u64
+}
+
+/// Returns the header declaring the exported slots.
+pub fn header() -> String {
+    inline_cache::ffi::CHeader::new()
+        .slot::<AtomicU64>("ffi_requests")
+        .slot::<Limits>("ffi_limits")
+        .to_string()
+}
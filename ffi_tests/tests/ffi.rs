@@ -0,0 +1,48 @@
+use std::{path::Path, process::Command};
+
+#[test]
+#[cfg_attr(any(miri, target_family = "wasm", windows), ignore)]
+fn c_program_uses_exported_slots() {
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    std::fs::write(out_dir.join("ffi_slots.h"), ffi_tests::header()).unwrap();
+
+    let mut build = Command::new(env!("CARGO"));
+    build.args(["build", "--offline", "--lib", "--manifest-path"]);
+    build.arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+    build.arg("--target-dir").arg(out_dir.join("target"));
+    if cfg!(feature = "force_fallback_impl") {
+        build.args(["--features", "force_fallback_impl"]);
+    }
+    let output = build.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let program = out_dir.join("main");
+    let status = Command::new("cc")
+        .arg("-std=c11")
+        .arg("-I")
+        .arg(&out_dir)
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/c/main.c"))
+        .arg(out_dir.join("target/debug/libffi_tests.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = Command::new("c++")
+        .args(["-std=c++11", "-fsyntax-only", "-I"])
+        .arg(&out_dir)
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/c/header.cpp"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&program).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2 5\n");
+}
@@ -0,0 +1,219 @@
+//! Singleton tables that are separate from the global one, for tests running in parallel.
+//!
+//! Entering a context sets a thread-local table that `table_entry` uses instead of the global
+//! table. The inline caches of the singleton functions are shared by all threads, so they must not
+//! hold a value while any thread has entered a context. Rather than checking for a context on the
+//! fast path, all caches are cleared when the first thread enters a context, and no cache is
+//! filled until the last one left. Calls outside of a context thus take the slow path while
+//! contexts are in use, and the fast path is unchanged otherwise.
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    ptr::null_mut,
+    sync::{
+        Mutex, MutexGuard, PoisonError, RwLock,
+        atomic::{AtomicPtr, Ordering::Release},
+    },
+    thread::{self, JoinHandle, Scope, ScopedJoinHandle},
+};
+
+use crate::{global_singleton_table, type_map::StaticTypeMap};
+
+/// A singleton table of its own, see `enter`.
+///
+/// Contexts are cheap to copy, all copies share the same table. Singleton values constructed in a
+/// context are never deallocated, like all singleton values, and neither is the table.
+#[derive(Clone, Copy)]
+pub struct SingletonContext {
+    table: &'static RwLock<StaticTypeMap>,
+}
+
+#[derive(Default)]
+struct Caches {
+    /// The number of threads that have currently entered a context.
+    entered: usize,
+    /// The addresses of the inline caches filled so far.
+    filled: HashSet<usize>,
+}
+
+static CACHES: Mutex<Option<Caches>> = Mutex::new(None);
+
+fn caches() -> MutexGuard<'static, Option<Caches>> {
+    CACHES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<SingletonContext>> = const { Cell::new(None) };
+}
+
+impl SingletonContext {
+    /// Creates a context with an empty singleton table.
+    pub fn new() -> Self {
+        Self {
+            table: Box::leak(Box::new(RwLock::new(StaticTypeMap::new()))),
+        }
+    }
+
+    /// Returns the context entered by the current thread, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.get()
+    }
+
+    /// Runs `f` with this context entered on the current thread.
+    ///
+    /// All singleton functions called by `f` on this thread return the values of this context,
+    /// constructing them as if they were never constructed before. This includes overrides, and
+    /// excludes threads spawned by `f` unless they are spawned through `spawn` or `spawn_scoped`.
+    /// Contexts can be nested, the previously entered context is restored when `f` returns or
+    /// panics.
+    ///
+    /// While any thread has entered a context, the singleton functions don't use their inline
+    /// caches, on all threads, so they are slower until the last context was left.
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Exit(Option<SingletonContext>);
+
+        impl Drop for Exit {
+            fn drop(&mut self) {
+                CURRENT.set(self.0);
+                if let Some(caches) = &mut *caches() {
+                    caches.entered -= 1;
+                }
+            }
+        }
+
+        {
+            let mut caches = caches();
+            let caches = caches.get_or_insert_default();
+            // No cache was filled since another thread entered a context
+            if caches.entered == 0 {
+                for &cache in &caches.filled {
+                    // All filled caches are `AtomicPtr`s, and the pointee type doesn't matter for
+                    // storing a null pointer
+                    let cache = unsafe { &*(cache as *const AtomicPtr<()>) };
+                    cache.store(null_mut(), Release);
+                }
+            }
+            caches.entered += 1;
+        }
+        let _exit = Exit(CURRENT.replace(Some(*self)));
+        f()
+    }
+
+    /// Spawns a thread that runs `f` with this context entered, see `std::thread::spawn`.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let context = *self;
+        thread::spawn(move || context.enter(f))
+    }
+
+    /// Spawns a scoped thread that runs `f` with this context entered, see `Scope::spawn`.
+    pub fn spawn_scoped<'scope, 'env, F, T>(
+        &self,
+        scope: &'scope Scope<'scope, 'env>,
+        f: F,
+    ) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let context = *self;
+        scope.spawn(move || context.enter(f))
+    }
+}
+
+impl Default for SingletonContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stores `value` in the inline cache `cache` unless a thread has entered a context, and returns
+/// whether it did.
+///
+/// All inline caches of the singleton functions must be filled through this, so that entering a
+/// context can clear them.
+pub(crate) fn fill<T>(cache: &'static AtomicPtr<T>, value: *mut T) -> bool {
+    let mut caches = caches();
+    let caches = caches.get_or_insert_default();
+    if caches.entered != 0 {
+        return false;
+    }
+    caches.filled.insert(cache as *const AtomicPtr<T> as usize);
+    cache.store(value, Release);
+    true
+}
+
+/// Returns the singleton table of the current thread.
+pub(crate) fn table() -> &'static RwLock<StaticTypeMap> {
+    match CURRENT.get() {
+        Some(context) => context.table,
+        None => global_singleton_table(),
+    }
+}
+
+/// Identifies the singleton table of the current thread, as long as it is in use.
+#[cfg(debug_assertions)]
+pub(crate) fn table_id() -> usize {
+    CURRENT
+        .get()
+        .map_or(0, |context| context.table as *const _ as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{singleton, singleton_with, try_singleton_with};
+
+    #[test]
+    fn separate_tables() {
+        #[derive(Default)]
+        struct Counter(usize);
+
+        assert_eq!(singleton_with(|| Counter(1)).0, 1);
+
+        let contexts = [SingletonContext::new(), SingletonContext::new()];
+        thread::scope(|scope| {
+            for (i, context) in contexts.iter().enumerate() {
+                context.spawn_scoped(scope, move || {
+                    assert_eq!(singleton_with(|| Counter(10 + i)).0, 10 + i);
+                    assert_eq!(singleton::<Counter>().0, 10 + i);
+                });
+            }
+        });
+
+        assert_eq!(singleton::<Counter>().0, 1);
+        for (i, context) in contexts.iter().enumerate() {
+            let value = context.enter(|| {
+                try_singleton_with::<Counter, ()>(|| panic!("not constructed"))
+                    .unwrap()
+                    .0
+            });
+            assert_eq!(value, 10 + i);
+        }
+    }
+
+    #[test]
+    fn nested_contexts() {
+        #[derive(Default)]
+        struct Name(&'static str);
+
+        let outer = SingletonContext::new();
+        let inner = SingletonContext::new();
+        outer.enter(|| {
+            assert_eq!(singleton_with(|| Name("outer")).0, "outer");
+            inner.enter(|| {
+                assert_eq!(singleton_with(|| Name("inner")).0, "inner");
+                let spawned = SingletonContext::current()
+                    .unwrap()
+                    .spawn(|| singleton::<Name>().0);
+                assert_eq!(spawned.join().unwrap(), "inner");
+            });
+            assert_eq!(singleton::<Name>().0, "outer");
+        });
+        assert!(SingletonContext::current().is_none());
+        assert_eq!(singleton::<Name>().0, "");
+    }
+}
@@ -0,0 +1,188 @@
+//! Detection of singleton initialization cycles in debug builds.
+//!
+//! Every thread keeps a stack of the singleton types it is constructing, and a global waits-for
+//! graph records which thread constructs which type and which type every blocked thread waits for.
+//! Waiting for a type that the waiting thread is already constructing, directly or through any
+//! number of other threads, would deadlock, so it panics with the chain of types instead. Every
+//! thread waits for at most one type and every type is constructed by at most one thread, so
+//! following the edges from the waited type finds every such cycle.
+//!
+//! This covers the waits of the synchronous singleton functions, including the attempt lock of
+//! `try_singleton_with`. `singleton_async` isn't covered: its calls wait by returning `Pending`,
+//! so a cycle between async initializations leaves the futures pending without blocking any
+//! thread. Cycles that also go through locks outside of this crate aren't detected either.
+#[cfg(debug_assertions)]
+pub(crate) use imp::{constructing, waiting};
+
+#[cfg(debug_assertions)]
+mod imp {
+    use std::{
+        any::{TypeId, type_name},
+        cell::RefCell,
+        collections::HashMap,
+        fmt,
+        sync::{Mutex, MutexGuard, PoisonError},
+        thread::{self, ThreadId},
+    };
+
+    use crate::{Unkeyed, context};
+
+    /// Identifies a singleton type and key within the singleton table of the current thread.
+    type Key = (usize, TypeId, TypeId);
+
+    type Type = (Key, Name);
+
+    fn key<T: 'static, K: 'static>() -> Key {
+        (context::table_id(), TypeId::of::<T>(), TypeId::of::<K>())
+    }
+
+    #[derive(Clone, Copy)]
+    struct Name {
+        value: &'static str,
+        key: Option<&'static str>,
+    }
+
+    impl Name {
+        fn of<T: 'static, K: 'static>() -> Self {
+            Self {
+                value: type_name::<T>(),
+                key: (TypeId::of::<K>() != TypeId::of::<Unkeyed>()).then(type_name::<K>),
+            }
+        }
+    }
+
+    impl fmt::Display for Name {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.key {
+                Some(key) => write!(f, "{}[K={key}]", self.value),
+                None => f.write_str(self.value),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Graph {
+        constructed_by: HashMap<Key, ThreadId>,
+        waiting_for: HashMap<ThreadId, Type>,
+    }
+
+    static GRAPH: Mutex<Option<Graph>> = Mutex::new(None);
+
+    thread_local! {
+        static CONSTRUCTING: RefCell<Vec<Type>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn graph() -> MutexGuard<'static, Option<Graph>> {
+        GRAPH.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn chain(types: impl IntoIterator<Item = Name>) -> String {
+        types
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    /// Records that the current thread is about to wait for the construction of `T` for the key
+    /// `K`.
+    ///
+    /// Panics if that would deadlock.
+    pub(crate) fn waiting<T: 'static, K: 'static>() -> Waiting {
+        let id = key::<T, K>();
+        let name = Name::of::<T, K>();
+
+        let stack = CONSTRUCTING.with_borrow(|stack| stack.clone());
+        if let Some(start) = stack
+            .iter()
+            .position(|&(constructing, _)| constructing == id)
+        {
+            let types = stack[start..].iter().map(|&(_, name)| name);
+            panic!(
+                "generic_singleton: singleton initialization cycle {}",
+                chain(types.chain([name]))
+            );
+        }
+
+        let current = thread::current().id();
+        let mut graph = graph();
+        let graph = graph.get_or_insert_default();
+
+        // Follows the waits-for edges, the cycle is complete when they lead back to this thread
+        let mut types = vec![name];
+        let mut waited = id;
+        while let Some(&thread) = graph.constructed_by.get(&waited) {
+            if thread == current {
+                let types = types.last().copied().into_iter().chain(types);
+                panic!(
+                    "generic_singleton: singleton initialization cycle between threads {}",
+                    chain(types)
+                );
+            }
+            let Some(&(next, next_name)) = graph.waiting_for.get(&thread) else {
+                break;
+            };
+            types.push(next_name);
+            waited = next;
+        }
+
+        graph.waiting_for.insert(current, (id, name));
+        Waiting(())
+    }
+
+    /// Records that the current thread constructs `T` for the key `K` until the returned guard is
+    /// dropped.
+    pub(crate) fn constructing<T: 'static, K: 'static>() -> Constructing {
+        let current = thread::current().id();
+        let mut graph = graph();
+        let graph = graph.get_or_insert_default();
+        graph.waiting_for.remove(&current);
+        graph.constructed_by.insert(key::<T, K>(), current);
+
+        CONSTRUCTING.with_borrow_mut(|stack| stack.push((key::<T, K>(), Name::of::<T, K>())));
+        Constructing(())
+    }
+
+    pub(crate) struct Waiting(());
+
+    impl Drop for Waiting {
+        fn drop(&mut self) {
+            if let Some(graph) = &mut *graph() {
+                graph.waiting_for.remove(&thread::current().id());
+            }
+        }
+    }
+
+    pub(crate) struct Constructing(());
+
+    impl Drop for Constructing {
+        fn drop(&mut self) {
+            let Some((id, _)) = CONSTRUCTING.with_borrow_mut(|stack| stack.pop()) else {
+                return;
+            };
+            if let Some(graph) = &mut *graph() {
+                graph.constructed_by.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) struct Waiting;
+
+#[cfg(not(debug_assertions))]
+#[allow(clippy::extra_unused_type_parameters)]
+#[inline(always)]
+pub(crate) fn waiting<T: 'static, K: 'static>() -> Waiting {
+    Waiting
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) struct Constructing;
+
+#[cfg(not(debug_assertions))]
+#[allow(clippy::extra_unused_type_parameters)]
+#[inline(always)]
+pub(crate) fn constructing<T: 'static, K: 'static>() -> Constructing {
+    Constructing
+}
@@ -1,13 +1,36 @@
-use std::sync::{
-    OnceLock, RwLock,
-    atomic::{AtomicPtr, Ordering::Relaxed},
+use std::{
+    any::TypeId,
+    marker::PhantomData,
+    ptr::null_mut,
+    sync::{
+        Mutex, MutexGuard, OnceLock, PoisonError, RwLock,
+        atomic::{
+            AtomicPtr,
+            Ordering::{AcqRel, Acquire},
+        },
+    },
+    task::{Poll, Waker},
 };
 
 use inline_cache::inline_cache;
+use inline_cache_abi::GENERIC_SINGLETON_TABLE;
 use type_map::StaticTypeMap;
 
+mod context;
+mod cycle;
+mod locator;
+mod overrides;
+mod registry;
+mod teardown;
 mod type_map;
 
+pub use context::SingletonContext;
+pub use locator::{provide, singleton_dyn};
+#[cfg(any(test, feature = "testing"))]
+pub use overrides::{OverrideGuard, override_singleton};
+pub use registry::{RegisterError, register_constructor, seal, singleton_registered};
+pub use teardown::{Teardown, shutdown, singleton_with_teardown};
+
 /// Returns the unique singleton value of type `T`.
 ///
 /// Uses `T::default()` to construct the singleton value if it hasn't been constructed before.
@@ -19,64 +42,338 @@ pub fn singleton<T: Default + Sync + 'static>() -> &'static T {
 /// Returns the unique singleton value of type `T`.
 ///
 /// Uses the `construct` argument to construct the singleton value if it hasn't been constructed
-/// before.
+/// before. A panic of `construct` propagates to the caller and leaves the value unconstructed, so
+/// a later call tries again.
 #[inline(always)]
 pub fn singleton_with<T: Sync + 'static>(construct: impl FnOnce() -> T) -> &'static T {
     let cache = inline_cache!(AtomicPtr<T>);
 
-    if let Some(cached_ptr) = unsafe { cache.load(Relaxed).as_ref() } {
+    // Acquire to make sure the singleton value is visible, pairs with the `Release` store of
+    // `fill_cache`
+    if let Some(cached_ptr) = unsafe { cache.load(Acquire).as_ref() } {
         return cached_ptr;
     };
 
-    fill_cache(cache, construct)
+    fill_cache(cache, construct, |_| {})
+}
+
+/// Returns the unique singleton value of type `T` for the key `K`.
+///
+/// Uses `T::default()` to construct the singleton value if it hasn't been constructed before, see
+/// `singleton_keyed_with`.
+#[inline(always)]
+pub fn singleton_keyed<T: Default + Sync + 'static, K: 'static>() -> &'static T {
+    singleton_keyed_with::<T, K>(Default::default)
+}
+
+/// Returns the unique singleton value of type `T` for the key `K`.
+///
+/// Works like `singleton_with`, but every key type has a singleton value of its own, so unrelated
+/// code using singletons of the same type can use a key type each instead of wrapper types. The
+/// singleton value of `singleton_with` is separate from all keyed values, and overrides don't apply
+/// to keyed values.
+#[inline(always)]
+pub fn singleton_keyed_with<T: Sync + 'static, K: 'static>(
+    construct: impl FnOnce() -> T,
+) -> &'static T {
+    let cache = inline_cache!(AtomicPtr<T>, K);
+
+    if let Some(cached_ptr) = unsafe { cache.load(Acquire).as_ref() } {
+        return cached_ptr;
+    };
+
+    fill_keyed_cache::<T, K>(cache, construct)
+}
+
+/// Returns the unique singleton value of type `T`, or the error of a failed construction.
+///
+/// Uses the `construct` argument to try to construct the singleton value if it hasn't been
+/// constructed before. Errors are not cached, so a later call tries again. Concurrent calls wait
+/// for the current attempt, and either all return its value or each try on their own after it
+/// failed.
+#[inline(always)]
+pub fn try_singleton_with<T: Sync + 'static, E>(
+    construct: impl FnOnce() -> Result<T, E>,
+) -> Result<&'static T, E> {
+    let cache = inline_cache!(AtomicPtr<T>);
+
+    if let Some(cached_ptr) = unsafe { cache.load(Acquire).as_ref() } {
+        return Ok(cached_ptr);
+    };
+
+    try_fill_cache(cache, construct)
 }
 
 #[inline(never)]
 #[cold]
-fn fill_cache<T: Sync + 'static>(
+pub(crate) fn fill_cache<T: Sync + 'static>(
     cache: &'static AtomicPtr<T>,
     construct: impl FnOnce() -> T,
+    constructed: impl FnOnce(&'static T),
 ) -> &'static T {
-    let singleton_ref = singleton_global(construct);
-    cache.store(singleton_ref as *const T as *mut T, Relaxed);
+    if let Some(overridden) = overrides::overridden::<T>() {
+        return overrides::publish(cache, overridden);
+    }
+    let singleton_ref = singleton_global::<T, Unkeyed>(construct, constructed);
+    overrides::publish(cache, singleton_ref)
+}
+
+#[inline(never)]
+#[cold]
+fn try_fill_cache<T: Sync + 'static, E>(
+    cache: &'static AtomicPtr<T>,
+    construct: impl FnOnce() -> Result<T, E>,
+) -> Result<&'static T, E> {
+    if let Some(overridden) = overrides::overridden::<T>() {
+        return Ok(overrides::publish(cache, overridden));
+    }
+    let singleton_ref = try_singleton_global(construct)?;
+    Ok(overrides::publish(cache, singleton_ref))
+}
+
+#[inline(never)]
+#[cold]
+fn fill_keyed_cache<T: Sync + 'static, K: 'static>(
+    cache: &'static AtomicPtr<T>,
+    construct: impl FnOnce() -> T,
+) -> &'static T {
+    let singleton_ref = singleton_global::<T, K>(construct, |_| {});
+    context::fill(cache, singleton_ref as *const T as *mut T);
     singleton_ref
 }
 
-static GLOBAL_SINGLETON_TABLE: RwLock<StaticTypeMap> = RwLock::new(StaticTypeMap::new());
+/// Returns the unique singleton value of type `T`, awaiting `init` to construct it.
+///
+/// Concurrent calls share a single initialization: they wait for the `init` future of the call
+/// that started first instead of polling their own. When that call is dropped before `init`
+/// completes, one of the waiting calls takes over with its own `init`. Waiting uses the wakers of
+/// the calling tasks only, so this works with any executor.
+#[inline(always)]
+pub async fn singleton_async<T: Sync + 'static>(init: impl Future<Output = T>) -> &'static T {
+    let cache = inline_cache!(AtomicPtr<T>);
+
+    if let Some(cached_ptr) = unsafe { cache.load(Acquire).as_ref() } {
+        return cached_ptr;
+    };
+
+    if let Some(overridden) = overrides::overridden::<T>() {
+        return overrides::publish(cache, overridden);
+    }
+    let singleton_ref = singleton_global_async(init).await;
+    overrides::publish(cache, singleton_ref)
+}
+
+/// Identifies the layout of `SharedTable`, which must change whenever that layout or the way it is
+/// used changes.
+const TABLE_LAYOUT: u64 = 1;
+
+/// The global singleton table, shared by all versions of this crate through the slot ABI, so that
+/// they agree on the singleton value of every type.
+#[repr(C)]
+struct SharedTable {
+    layout: u64,
+    table: RwLock<StaticTypeMap>,
+}
 
 #[inline(never)]
-fn singleton_global<T: Sync + 'static>(construct: impl FnOnce() -> T) -> &'static T {
-    loop {
-        let found = {
-            let Ok(read) = GLOBAL_SINGLETON_TABLE.read() else {
-                std::process::abort();
+fn global_singleton_table() -> &'static RwLock<StaticTypeMap> {
+    let mut shared = GENERIC_SINGLETON_TABLE.load(Acquire).cast::<SharedTable>();
+    if shared.is_null() {
+        let new = Box::into_raw(Box::new(SharedTable {
+            layout: TABLE_LAYOUT,
+            table: RwLock::new(StaticTypeMap::new()),
+        }));
+        shared =
+            match GENERIC_SINGLETON_TABLE.compare_exchange(null_mut(), new.cast(), AcqRel, Acquire)
+            {
+                Ok(_) => new,
+                Err(existing) => {
+                    drop(unsafe { Box::from_raw(new) });
+                    existing.cast()
+                }
             };
+    }
 
-            read.get::<OnceLock<&'static T>>()
-        };
+    // The layout tag is the first field in all versions, so it can always be read
+    let layout = unsafe { (&raw const (*shared).layout).read() };
+    if layout != TABLE_LAYOUT {
+        panic!(
+            "generic_singleton: incompatible versions of generic_singleton are used in the same \
+             program, found singleton table layout {layout}, expected {TABLE_LAYOUT}"
+        );
+    }
+    unsafe { &(*shared).table }
+}
+
+/// Returns the entry of type `V` of the singleton table of the current thread, which is the global
+/// one unless a `SingletonContext` is entered, inserting `new()` if there is none.
+#[inline(never)]
+fn table_entry<V: Sync + 'static>(new: impl FnOnce() -> V) -> &'static V {
+    let table = context::table();
+    {
+        // Inserting an entry is idempotent and constructors don't run while the table is locked,
+        // so the table is consistent even if a panic poisoned its lock
+        let read = table.read().unwrap_or_else(PoisonError::into_inner);
 
-        // We already dropped the read guard to make sure we're not holding any global lock while
-        // running the (potentially expensive) constructor
-        if let Some(found) = found {
-            let found = *found.get_or_init(
-                #[cold]
-                move || Box::leak(Box::new(construct())),
-            );
+        if let Some(found) = read.get::<V>() {
             return found;
         }
+    }
 
-        {
-            let Ok(mut write) = GLOBAL_SINGLETON_TABLE.write() else {
-                std::process::abort();
-            };
+    let mut write = table.write().unwrap_or_else(PoisonError::into_inner);
 
-            write.get_or_insert_with::<OnceLock<&'static T>>(|| {
-                Box::leak(Box::new(OnceLock::new()))
-            });
+    write.get_or_insert_with::<V>(|| Box::leak(Box::new(new())))
+}
+
+/// The key of the singleton functions without a key, which no caller can use as a key type.
+pub(crate) struct Unkeyed;
+
+/// The table entry of the singleton value of type `T` for the key `K`. It consists of `std` types
+/// only, so that all versions of this crate use the same entry.
+type Keyed<T, K> = (OnceLock<&'static T>, PhantomData<fn() -> K>);
+
+/// Returns the cell of the singleton value of type `T` for the key `K`.
+///
+/// Values without a key are stored in a plain `OnceLock`, which is the entry all versions of this
+/// crate use for them, whether or not they support keys.
+fn value_cell<T: Sync + 'static, K: 'static>() -> &'static OnceLock<&'static T> {
+    if TypeId::of::<K>() == TypeId::of::<Unkeyed>() {
+        table_entry::<OnceLock<&'static T>>(OnceLock::new)
+    } else {
+        &table_entry::<Keyed<T, K>>(|| (OnceLock::new(), PhantomData)).0
+    }
+}
+
+/// Returns the singleton value of type `T` for the key `K`, constructing it if needed.
+/// `constructed` is called with the value by the thread that constructed it.
+#[inline(never)]
+fn singleton_global<T: Sync + 'static, K: 'static>(
+    construct: impl FnOnce() -> T,
+    constructed: impl FnOnce(&'static T),
+) -> &'static T {
+    let cell = value_cell::<T, K>();
+    if let Some(&value) = cell.get() {
+        return value;
+    }
+
+    // `table_entry` already dropped its guard to make sure we're not holding any global lock while
+    // running the (potentially expensive) constructor
+    let _waiting = cycle::waiting::<T, K>();
+    cell.get_or_init(
+        #[cold]
+        move || {
+            let _constructing = cycle::constructing::<T, K>();
+            let value = Box::leak(Box::new(construct()));
+            constructed(value);
+            value
+        },
+    )
+}
+
+/// Serializes the attempts of `try_singleton_with` to construct the singleton value of type `T`.
+struct TryInitLock<T>(Mutex<()>, PhantomData<fn() -> T>);
+
+#[inline(never)]
+fn try_singleton_global<T: Sync + 'static, E>(
+    construct: impl FnOnce() -> Result<T, E>,
+) -> Result<&'static T, E> {
+    let cell = value_cell::<T, Unkeyed>();
+    if let Some(&value) = cell.get() {
+        return Ok(value);
+    }
+
+    let lock = table_entry::<TryInitLock<T>>(|| TryInitLock(Mutex::new(()), PhantomData));
+    let _attempt = {
+        let _waiting = cycle::waiting::<T, Unkeyed>();
+        // The lock doesn't protect any data, so a panicking constructor can't leave anything
+        // broken
+        lock.0.lock().unwrap_or_else(PoisonError::into_inner)
+    };
+
+    // Another attempt or `singleton_with` may have succeeded while waiting for the lock
+    if let Some(&value) = cell.get() {
+        return Ok(value);
+    }
+
+    let _constructing = cycle::constructing::<T, Unkeyed>();
+    let mut constructed = Some(construct()?);
+    Ok(*cell.get_or_init(|| Box::leak(Box::new(constructed.take().unwrap()))))
+}
+
+/// Serializes the initializations of `singleton_async` for the singleton value of type `T`.
+struct AsyncInitLock<T> {
+    state: Mutex<AsyncInitState>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Default)]
+struct AsyncInitState {
+    initializing: bool,
+    waiters: Vec<Waker>,
+}
+
+impl<T> AsyncInitLock<T> {
+    fn state(&self) -> MutexGuard<'_, AsyncInitState> {
+        // Nothing can panic while the state is locked, but there is no reason to give up if it did
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Held while initializing, wakes the waiting calls when the initialization completes or is
+/// cancelled.
+struct AsyncInitGuard<T: 'static>(&'static AsyncInitLock<T>);
+
+impl<T> Drop for AsyncInitGuard<T> {
+    fn drop(&mut self) {
+        let mut state = self.0.state();
+        state.initializing = false;
+        for waker in state.waiters.drain(..) {
+            waker.wake();
         }
     }
 }
 
+async fn singleton_global_async<T: Sync + 'static>(init: impl Future<Output = T>) -> &'static T {
+    let cell = value_cell::<T, Unkeyed>();
+    let lock = table_entry::<AsyncInitLock<T>>(|| AsyncInitLock {
+        state: Mutex::default(),
+        _marker: PhantomData,
+    });
+
+    // Waits until the singleton value is ready or there is no other initialization in progress
+    let guard = std::future::poll_fn(|cx| {
+        if let Some(&value) = cell.get() {
+            return Poll::Ready(Err(value));
+        }
+        let mut state = lock.state();
+        if state.initializing {
+            if !state
+                .waiters
+                .iter()
+                .any(|waker| waker.will_wake(cx.waker()))
+            {
+                state.waiters.push(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+        state.initializing = true;
+        Poll::Ready(Ok(AsyncInitGuard(lock)))
+    })
+    .await;
+    let _guard = match guard {
+        Ok(guard) => guard,
+        Err(value) => return value,
+    };
+
+    // The previous initialization may have completed right before it released the lock
+    if let Some(&value) = cell.get() {
+        return value;
+    }
+
+    let mut constructed = Some(init.await);
+    cell.get_or_init(|| Box::leak(Box::new(constructed.take().unwrap())))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,4 +390,393 @@ mod tests {
         assert_eq!(singleton_with::<A>(|| A(5)).0, 1);
         assert_eq!(singleton_with::<B>(|| B(6)).0, 3);
     }
+
+    #[test]
+    fn keyed_singletons() {
+        // Other tests may use singletons of any `std` type, so the value type is local
+        #[derive(Default)]
+        struct Log(Mutex<Vec<String>>);
+        struct Warnings;
+        struct Errors;
+
+        fn log<K: 'static>(message: &str) -> Vec<String> {
+            let mut log = singleton_keyed::<Log, K>().0.lock().unwrap();
+            log.push(message.into());
+            log.clone()
+        }
+
+        assert_eq!(log::<Warnings>("slow"), ["slow"]);
+        assert_eq!(log::<Errors>("failed"), ["failed"]);
+        assert_eq!(log::<Warnings>("slower"), ["slow", "slower"]);
+        assert!(singleton::<Log>().0.lock().unwrap().is_empty());
+
+        // Constructing a keyed value may use the value of the same type for another key
+        let total = singleton_keyed_with::<usize, Errors>(|| {
+            singleton_keyed_with::<usize, Warnings>(|| 2) + 1
+        });
+        assert_eq!(*total, 3);
+        assert_eq!(*singleton_keyed::<usize, Warnings>(), 2);
+
+        // `()` is a key like any other, separate from the value without a key
+        struct Level(u8);
+        assert_eq!(singleton_with(|| Level(1)).0, 1);
+        assert_eq!(singleton_keyed_with::<Level, ()>(|| Level(2)).0, 2);
+    }
+
+    #[test]
+    fn concurrent_construction() {
+        struct Probe(Vec<usize>);
+
+        let values: Vec<&'static Probe> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|i| scope.spawn(move || singleton_with(|| Probe(vec![i; 64]))))
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        let first = values[0];
+        for value in values {
+            assert!(std::ptr::eq(value, first));
+            assert!(value.0.iter().all(|&i| i == first.0[0]));
+        }
+    }
+
+    #[test]
+    fn try_singleton_with_retries() {
+        struct A(usize);
+
+        assert_eq!(
+            try_singleton_with::<A, _>(|| Err("first")).err(),
+            Some("first")
+        );
+        assert_eq!(
+            try_singleton_with::<A, &str>(|| Ok(A(2))).ok().map(|a| a.0),
+            Some(2)
+        );
+        assert_eq!(
+            try_singleton_with::<A, &str>(|| Ok(A(3))).ok().map(|a| a.0),
+            Some(2)
+        );
+        assert_eq!(singleton_with::<A>(|| A(4)).0, 2);
+    }
+
+    #[test]
+    fn try_singleton_with_concurrent() {
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering::Relaxed},
+            thread,
+            time::Duration,
+        };
+
+        struct B(usize);
+
+        static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+        static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+        let results: Vec<Result<usize, usize>> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        try_singleton_with(|| {
+                            assert_eq!(ACTIVE.fetch_add(1, Relaxed), 0);
+                            thread::sleep(Duration::from_millis(5));
+                            ACTIVE.fetch_sub(1, Relaxed);
+                            // The first two attempts fail
+                            match ATTEMPTS.fetch_add(1, Relaxed) {
+                                attempt @ 0..2 => Err(attempt),
+                                attempt => Ok(B(attempt)),
+                            }
+                        })
+                        .map(|b| b.0)
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        assert_eq!(ATTEMPTS.load(Relaxed), 3);
+        let mut errors: Vec<usize> = results.iter().filter_map(|r| r.err()).collect();
+        errors.sort();
+        assert_eq!(errors, [0, 1]);
+        assert!(
+            results
+                .iter()
+                .filter_map(|r| r.ok())
+                .all(|value| value == 2)
+        );
+    }
+
+    #[test]
+    fn singleton_async_shared_initialization() {
+        use std::{
+            future::Future,
+            pin::pin,
+            sync::{
+                Arc,
+                atomic::{AtomicBool, Ordering::Relaxed},
+            },
+            task::{Context, Wake},
+        };
+
+        struct Flag(AtomicBool);
+
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Relaxed);
+            }
+        }
+
+        struct C(usize);
+
+        let woken = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // The first call starts the initialization and is dropped before it completes
+        let mut first = Box::pin(singleton_async(async {
+            std::future::pending::<()>().await;
+            C(1)
+        }));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+
+        let mut second = pin!(singleton_async(async { C(2) }));
+        let mut third = pin!(singleton_async(async { C(3) }));
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(third.as_mut().poll(&mut cx).is_pending());
+        assert!(!woken.0.load(Relaxed));
+
+        drop(first);
+        assert!(woken.0.load(Relaxed));
+
+        let Poll::Ready(second) = second.as_mut().poll(&mut cx) else {
+            panic!("second call didn't take over the initialization");
+        };
+        assert_eq!(second.0, 2);
+
+        // Waiting calls return the value of the initialization they waited for
+        let Poll::Ready(third) = third.as_mut().poll(&mut cx) else {
+            panic!("third call is still waiting");
+        };
+        assert!(std::ptr::eq(second, third));
+        assert!(std::ptr::eq(singleton_with(|| C(4)), second));
+    }
+
+    #[cfg(debug_assertions)]
+    fn panic_message(result: std::thread::Result<()>) -> String {
+        let payload = result.unwrap_err();
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn initialization_cycle() {
+        struct A;
+        struct B;
+
+        impl Default for A {
+            fn default() -> Self {
+                singleton::<B>();
+                A
+            }
+        }
+
+        impl Default for B {
+            fn default() -> Self {
+                singleton::<A>();
+                B
+            }
+        }
+
+        let message = panic_message(std::panic::catch_unwind(|| {
+            singleton::<A>();
+        }));
+        let a = std::any::type_name::<A>();
+        let b = std::any::type_name::<B>();
+        assert!(
+            message.ends_with(&format!("cycle {a} -> {b} -> {a}")),
+            "{message}"
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn initialization_cycle_between_threads() {
+        use std::{
+            sync::{
+                Barrier,
+                atomic::{AtomicBool, Ordering::Relaxed},
+            },
+            thread,
+        };
+
+        static BARRIER: Barrier = Barrier::new(2);
+
+        struct C;
+        struct D;
+
+        // Both threads start constructing before either requests the other type
+        impl Default for C {
+            fn default() -> Self {
+                static STARTED: AtomicBool = AtomicBool::new(false);
+                if !STARTED.swap(true, Relaxed) {
+                    BARRIER.wait();
+                }
+                singleton::<D>();
+                C
+            }
+        }
+
+        impl Default for D {
+            fn default() -> Self {
+                static STARTED: AtomicBool = AtomicBool::new(false);
+                if !STARTED.swap(true, Relaxed) {
+                    BARRIER.wait();
+                }
+                singleton::<C>();
+                D
+            }
+        }
+
+        let c = thread::spawn(|| {
+            singleton::<C>();
+        });
+        let d = thread::spawn(|| {
+            singleton::<D>();
+        });
+
+        let messages = [panic_message(c.join()), panic_message(d.join())];
+        let (c, d) = (std::any::type_name::<C>(), std::any::type_name::<D>());
+        assert!(
+            messages.iter().any(
+                |message| message.ends_with(&format!("threads {c} -> {d} -> {c}"))
+                    || message.ends_with(&format!("threads {d} -> {c} -> {d}"))
+            ),
+            "{messages:?}"
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn initialization_cycle_between_three_threads() {
+        use std::{
+            sync::{
+                Barrier,
+                atomic::{AtomicBool, Ordering::Relaxed},
+            },
+            thread,
+        };
+
+        static BARRIER: Barrier = Barrier::new(3);
+
+        // Each type is first constructed by a thread of its own, and needs the next one
+        macro_rules! cycle_types {
+            ($($T:ident -> $U:ident),*) => {
+                $(
+                    struct $T;
+
+                    impl Default for $T {
+                        fn default() -> Self {
+                            static STARTED: AtomicBool = AtomicBool::new(false);
+                            if !STARTED.swap(true, Relaxed) {
+                                BARRIER.wait();
+                            }
+                            singleton::<$U>();
+                            $T
+                        }
+                    }
+                )*
+            };
+        }
+
+        cycle_types!(P -> Q, Q -> R, R -> P);
+
+        let threads = [
+            thread::spawn(|| {
+                singleton::<P>();
+            }),
+            thread::spawn(|| {
+                singleton::<Q>();
+            }),
+            thread::spawn(|| {
+                singleton::<R>();
+            }),
+        ];
+        let messages = threads.map(|thread| panic_message(thread.join()));
+        let [p, q, r] = [
+            std::any::type_name::<P>(),
+            std::any::type_name::<Q>(),
+            std::any::type_name::<R>(),
+        ];
+        let chains = [
+            format!("threads {p} -> {q} -> {r} -> {p}"),
+            format!("threads {q} -> {r} -> {p} -> {q}"),
+            format!("threads {r} -> {p} -> {q} -> {r}"),
+        ];
+        assert!(
+            messages
+                .iter()
+                .any(|message| chains.iter().any(|chain| message.ends_with(chain))),
+            "{messages:?}"
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn try_initialization_cycle() {
+        struct S;
+        struct U;
+
+        impl Default for U {
+            fn default() -> Self {
+                let _ = try_singleton_with::<S, ()>(|| Ok(S));
+                U
+            }
+        }
+
+        let message = panic_message(std::panic::catch_unwind(|| {
+            let _ = try_singleton_with::<S, ()>(|| {
+                singleton::<U>();
+                Ok(S)
+            });
+        }));
+        let s = std::any::type_name::<S>();
+        let u = std::any::type_name::<U>();
+        assert!(
+            message.ends_with(&format!("cycle {s} -> {u} -> {s}")),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn constructor_panic() {
+        #[derive(Debug)]
+        struct E(usize);
+        struct F(usize);
+
+        let result = std::panic::catch_unwind(|| singleton_with::<E>(|| panic!("constructing E")));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"constructing E"));
+
+        // The failed singleton can be constructed later, and other singletons are unaffected
+        assert_eq!(singleton_with(|| F(1)).0, 1);
+        assert_eq!(singleton_with(|| E(2)).0, 2);
+        assert_eq!(singleton_with(|| E(3)).0, 2);
+    }
+
+    #[test]
+    fn poisoned_table() {
+        struct G(usize);
+
+        let result = std::panic::catch_unwind(|| {
+            let _write = global_singleton_table().write();
+            panic!("poisoning the table");
+        });
+        assert!(result.is_err());
+        assert!(global_singleton_table().is_poisoned());
+
+        assert_eq!(singleton_with(|| G(1)).0, 1);
+        assert_eq!(singleton_with(|| G(2)).0, 1);
+    }
 }
@@ -0,0 +1,129 @@
+//! Singleton values of unsized types, typically trait objects, provided at startup.
+//!
+//! The singleton table and the inline caches can only hold thin pointers, so the table entry of a
+//! provided value is a `&'static T`, stored under its own `TypeId`, and the caches point to it.
+use std::{
+    any::type_name,
+    sync::{
+        PoisonError,
+        atomic::{AtomicPtr, Ordering::Acquire},
+    },
+};
+
+use inline_cache::inline_cache;
+
+use crate::context;
+
+/// Makes `value` the singleton value of type `T` for `singleton_dyn`.
+///
+/// `T` is usually a trait object type, e.g. `provide::<dyn Storage>(Box::new(DiskStorage))`. Fails
+/// and returns `value` if a value was already provided for `T`.
+pub fn provide<T: ?Sized + Sync + 'static>(value: Box<T>) -> Result<(), Box<T>> {
+    let mut table = context::table()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    if table.get::<&'static T>().is_some() {
+        return Err(value);
+    }
+    let value: &'static T = Box::leak(value);
+    table.get_or_insert_with::<&'static T>(|| Box::leak(Box::new(value)));
+    Ok(())
+}
+
+/// Returns the singleton value of type `T` provided by `provide`.
+///
+/// Panics if no value was provided for `T`.
+#[inline(always)]
+pub fn singleton_dyn<T: ?Sized + Sync + 'static>() -> &'static T {
+    let cache = inline_cache!(AtomicPtr<&'static T>);
+
+    if let Some(&cached) = unsafe { cache.load(Acquire).as_ref() } {
+        return cached;
+    };
+
+    fill_dyn_cache(cache)
+}
+
+#[inline(never)]
+#[cold]
+fn fill_dyn_cache<T: ?Sized + Sync + 'static>(cache: &'static AtomicPtr<&'static T>) -> &'static T {
+    let provided = context::table()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get::<&'static T>();
+    let Some(value) = provided else {
+        panic!(
+            "generic_singleton: no value was provided for the singleton {}, see `provide`",
+            type_name::<T>()
+        );
+    };
+    context::fill(cache, value as *const &'static T as *mut &'static T);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::catch_unwind;
+
+    use super::*;
+    use crate::SingletonContext;
+
+    trait Storage: Sync {
+        fn name(&self) -> String;
+    }
+
+    struct DiskStorage(&'static str);
+
+    impl Storage for DiskStorage {
+        fn name(&self) -> String {
+            format!("disk at {}", self.0)
+        }
+    }
+
+    struct MemoryStorage;
+
+    impl Storage for MemoryStorage {
+        fn name(&self) -> String {
+            "memory".into()
+        }
+    }
+
+    #[test]
+    fn provided_trait_objects() {
+        fn storage() -> String {
+            singleton_dyn::<dyn Storage>().name()
+        }
+
+        assert!(provide::<dyn Storage>(Box::new(DiskStorage("/var/lib"))).is_ok());
+        assert!(provide::<dyn Storage>(Box::new(MemoryStorage)).is_err());
+        assert_eq!(storage(), "disk at /var/lib");
+        assert_eq!(storage(), "disk at /var/lib");
+
+        let context = SingletonContext::new();
+        assert!(
+            context
+                .enter(|| provide::<dyn Storage>(Box::new(MemoryStorage)))
+                .is_ok()
+        );
+        assert_eq!(context.enter(storage), "memory");
+        assert_eq!(storage(), "disk at /var/lib");
+
+        provide::<str>("unsized".into()).unwrap();
+        assert_eq!(singleton_dyn::<str>(), "unsized");
+    }
+
+    #[test]
+    fn nothing_provided() {
+        trait Clock: Sync {}
+
+        let result = catch_unwind(|| {
+            singleton_dyn::<dyn Clock>();
+        });
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        let expected = format!(
+            "no value was provided for the singleton {}, see `provide`",
+            type_name::<dyn Clock>()
+        );
+        assert!(message.ends_with(&expected), "{message}");
+    }
+}
@@ -0,0 +1,171 @@
+//! Replacing singleton values in tests, enabled by the `testing` feature.
+//!
+//! Every inline cache of a singleton type registers itself when it is filled, so that overriding
+//! the value can update all caches. Overridden values are never deallocated, like all singleton
+//! values, so references returned before an override stay valid.
+use std::sync::atomic::AtomicPtr;
+
+use crate::context;
+
+#[cfg(any(test, feature = "testing"))]
+pub use imp::{OverrideGuard, override_singleton};
+#[cfg(any(test, feature = "testing"))]
+pub(crate) use imp::{overridden, publish};
+
+#[cfg(any(test, feature = "testing"))]
+mod imp {
+    use std::{
+        ptr::null_mut,
+        sync::{Mutex, MutexGuard, PoisonError},
+    };
+
+    use super::*;
+    use crate::table_entry;
+
+    struct Overrides<T: 'static> {
+        value: Option<&'static T>,
+        /// Incremented by every change of `value`.
+        generation: u64,
+        caches: Vec<&'static AtomicPtr<T>>,
+    }
+
+    fn overrides<T: Sync + 'static>() -> MutexGuard<'static, Overrides<T>> {
+        table_entry::<Mutex<Overrides<T>>>(|| {
+            Mutex::new(Overrides {
+                value: None,
+                generation: 0,
+                caches: Vec::new(),
+            })
+        })
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replaces the value of all caches of `T`, clearing them for `None`.
+    fn set<T: Sync + 'static>(value: Option<&'static T>) -> Option<&'static T> {
+        let mut overrides = overrides::<T>();
+        let ptr = value.map_or(null_mut(), |value| value as *const T as *mut T);
+        for &cache in &overrides.caches {
+            context::fill(cache, ptr);
+        }
+        overrides.generation += 1;
+        std::mem::replace(&mut overrides.value, value)
+    }
+
+    pub(crate) fn overridden<T: Sync + 'static>() -> Option<&'static T> {
+        overrides::<T>().value
+    }
+
+    pub(crate) fn publish<T: Sync + 'static>(
+        cache: &'static AtomicPtr<T>,
+        value: &'static T,
+    ) -> &'static T {
+        loop {
+            let (published, generation) = {
+                let mut overrides = overrides::<T>();
+                if !overrides
+                    .caches
+                    .iter()
+                    .any(|registered| std::ptr::eq(*registered, cache))
+                {
+                    overrides.caches.push(cache);
+                }
+                (overrides.value.unwrap_or(value), overrides.generation)
+            };
+
+            // Stores without holding the lock. Overrides in the meantime may have been
+            // overwritten, so this retries until there were none.
+            context::fill(cache, published as *const T as *mut T);
+            if overrides::<T>().generation == generation {
+                return published;
+            }
+        }
+    }
+
+    /// Makes all singleton functions return `value` for the type `T` from now on, whether or not
+    /// the singleton value was constructed already.
+    pub fn override_singleton<T: Sync + 'static>(value: T) {
+        set(Some(Box::leak(Box::new(value))));
+    }
+
+    /// Overrides the singleton value of type `T` until dropped, see `override_singleton`.
+    ///
+    /// Dropping the guard restores the override or singleton value that was in effect when it was
+    /// created. Guards for the same type must be dropped in the reverse order of their creation,
+    /// so this is meant for single-threaded tests.
+    pub struct OverrideGuard<T: Sync + 'static> {
+        previous: Option<&'static T>,
+    }
+
+    impl<T: Sync + 'static> OverrideGuard<T> {
+        pub fn new(value: T) -> Self {
+            Self {
+                previous: set(Some(Box::leak(Box::new(value)))),
+            }
+        }
+    }
+
+    impl<T: Sync + 'static> Drop for OverrideGuard<T> {
+        fn drop(&mut self) {
+            set(self.previous);
+        }
+    }
+}
+
+#[cfg(not(any(test, feature = "testing")))]
+#[inline(always)]
+pub(crate) fn overridden<T: Sync + 'static>() -> Option<&'static T> {
+    None
+}
+
+#[cfg(not(any(test, feature = "testing")))]
+#[inline(always)]
+pub(crate) fn publish<T: Sync + 'static>(
+    cache: &'static AtomicPtr<T>,
+    value: &'static T,
+) -> &'static T {
+    context::fill(cache, value as *const T as *mut T);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{singleton, singleton_with, try_singleton_with};
+
+    #[test]
+    fn override_before_first_use() {
+        struct Clock(u64);
+
+        override_singleton(Clock(10));
+        assert_eq!(singleton_with(|| Clock(0)).0, 10);
+        assert_eq!(
+            try_singleton_with::<Clock, ()>(|| panic!("not constructed"))
+                .unwrap()
+                .0,
+            10
+        );
+    }
+
+    #[test]
+    fn scoped_overrides() {
+        #[derive(Default)]
+        struct Config(&'static str);
+
+        fn config() -> &'static str {
+            singleton::<Config>().0
+        }
+
+        assert_eq!(config(), "");
+        {
+            let _outer = OverrideGuard::new(Config("outer"));
+            assert_eq!(config(), "outer");
+            {
+                let _inner = OverrideGuard::new(Config("inner"));
+                assert_eq!(config(), "inner");
+            }
+            assert_eq!(config(), "outer");
+        }
+        assert_eq!(config(), "");
+    }
+}
@@ -0,0 +1,173 @@
+//! Constructors registered at startup for `singleton_registered`.
+//!
+//! Registrations are entries of the singleton table, so a `SingletonContext` has registrations of
+//! its own and starts unsealed. For types without a registration of its own, a context uses the
+//! global registration, typically made at startup.
+use std::{
+    any::type_name,
+    fmt,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use crate::{global_singleton_table, singleton_with, table_entry};
+
+/// Returns the unique singleton value of type `T`, constructed by the constructor registered with
+/// `register_constructor`.
+///
+/// Panics if no constructor was registered for `T` by the time the value is first constructed.
+///
+/// The registered constructor only runs if the value isn't constructed yet. If another singleton
+/// function, e.g. `singleton_with::<T>`, constructs the value of type `T` before this is first
+/// called, its value is returned, even if that happened before the registration.
+#[inline(always)]
+pub fn singleton_registered<T: Sync + 'static>() -> &'static T {
+    singleton_with(construct_registered::<T>)
+}
+
+/// Registers the constructor of the singleton value of type `T` for `singleton_registered`.
+///
+/// Meant to be called once at startup, before the value is used. Fails if a constructor was
+/// already registered for `T` or after `seal`.
+pub fn register_constructor<T: Sync + 'static>(construct: fn() -> T) -> Result<(), RegisterError> {
+    // Sealing locks this too, so no registration can complete after `seal` returned
+    let sealed = sealed().lock().unwrap_or_else(PoisonError::into_inner);
+    if *sealed {
+        return Err(RegisterError::Sealed(type_name::<T>()));
+    }
+    constructor::<T>()
+        .set(construct)
+        .map_err(|_| RegisterError::AlreadyRegistered(type_name::<T>()))
+}
+
+/// Makes all later calls of `register_constructor` fail.
+pub fn seal() {
+    *sealed().lock().unwrap_or_else(PoisonError::into_inner) = true;
+}
+
+/// The reason `register_constructor` failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    /// A constructor was already registered for the type with this name.
+    AlreadyRegistered(&'static str),
+    /// `seal` was called before registering a constructor for the type with this name.
+    Sealed(&'static str),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyRegistered(name) => {
+                write!(
+                    f,
+                    "a constructor was already registered for the singleton {name}"
+                )
+            }
+            Self::Sealed(name) => write!(
+                f,
+                "cannot register a constructor for the singleton {name} after seal"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+/// Whether `seal` was called.
+struct Sealed(Mutex<bool>);
+
+fn sealed() -> &'static Mutex<bool> {
+    &table_entry::<Sealed>(|| Sealed(Mutex::new(false))).0
+}
+
+fn constructor<T: Sync + 'static>() -> &'static OnceLock<fn() -> T> {
+    table_entry::<OnceLock<fn() -> T>>(OnceLock::new)
+}
+
+/// Returns the constructor registered for `T` in the singleton table of the current thread, or in
+/// the global table if there is none.
+fn registered<T: Sync + 'static>() -> Option<fn() -> T> {
+    if let Some(&construct) = constructor::<T>().get() {
+        return Some(construct);
+    }
+    global_singleton_table()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get::<OnceLock<fn() -> T>>()?
+        .get()
+        .copied()
+}
+
+#[inline(never)]
+#[cold]
+fn construct_registered<T: Sync + 'static>() -> T {
+    let Some(construct) = registered::<T>() else {
+        panic!(
+            "generic_singleton: no constructor was registered for the singleton {}",
+            type_name::<T>()
+        );
+    };
+    construct()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::catch_unwind;
+
+    use super::*;
+    use crate::SingletonContext;
+
+    #[test]
+    fn registered_constructor() {
+        struct Port(u16);
+
+        register_constructor(|| Port(8080)).unwrap();
+        assert_eq!(
+            register_constructor(|| Port(0)),
+            Err(RegisterError::AlreadyRegistered(type_name::<Port>()))
+        );
+        assert_eq!(singleton_registered::<Port>().0, 8080);
+
+        // A context uses the global registration, unless it has one of its own
+        struct Timeout(u64);
+
+        register_constructor(|| Timeout(30)).unwrap();
+        SingletonContext::new().enter(|| {
+            assert_eq!(singleton_registered::<Timeout>().0, 30);
+            assert_eq!(singleton_registered::<Port>().0, 8080);
+        });
+        SingletonContext::new().enter(|| {
+            register_constructor(|| Port(9090)).unwrap();
+            assert_eq!(singleton_registered::<Port>().0, 9090);
+        });
+    }
+
+    #[test]
+    fn sealed_registrations() {
+        struct Host(&'static str);
+        struct Missing;
+
+        SingletonContext::new().enter(|| {
+            register_constructor(|| Host("localhost")).unwrap();
+            seal();
+            assert_eq!(
+                register_constructor(|| Missing),
+                Err(RegisterError::Sealed(type_name::<Missing>()))
+            );
+            assert_eq!(singleton_registered::<Host>().0, "localhost");
+
+            let result = catch_unwind(|| {
+                singleton_registered::<Missing>();
+            });
+            let message = result.unwrap_err().downcast::<String>().unwrap();
+            let expected = format!(
+                "no constructor was registered for the singleton {}",
+                type_name::<Missing>()
+            );
+            assert!(message.ends_with(&expected), "{message}");
+        });
+
+        // Sealing the context leaves the global registrations open
+        struct Open;
+        register_constructor(|| Open).unwrap();
+    }
+}
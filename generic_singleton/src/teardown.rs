@@ -0,0 +1,116 @@
+use std::{
+    any::type_name,
+    panic::{AssertUnwindSafe, catch_unwind, resume_unwind},
+    sync::{
+        Mutex, Once, PoisonError,
+        atomic::{
+            AtomicBool, AtomicPtr,
+            Ordering::{Acquire, Release},
+        },
+    },
+};
+
+use inline_cache::inline_cache;
+
+use crate::fill_cache;
+
+/// Singleton values that need to clean up at process exit, see `singleton_with_teardown`.
+pub trait Teardown: Sync + 'static {
+    /// Flushes buffers, removes temporary files and similar.
+    ///
+    /// Singleton values are never deallocated, as references to them may still exist, so this
+    /// takes `&self`. Values that need to be dropped can be kept in a `Mutex<Option<_>>` and taken
+    /// here.
+    fn teardown(&self);
+}
+
+/// Returns the unique singleton value of type `T`, registering it for teardown.
+///
+/// Works like `singleton_with`, but the value is torn down by `shutdown`, or at process exit if
+/// `shutdown` wasn't called. Values are torn down in the reverse order of their construction, so a
+/// value is torn down before the values its constructor used. Calling this after `shutdown`
+/// panics. The value must not be accessed through `singleton_with` and `singleton`, which neither
+/// register it nor check for `shutdown`.
+#[inline(always)]
+pub fn singleton_with_teardown<T: Teardown>(construct: impl FnOnce() -> T) -> &'static T {
+    if SHUT_DOWN.load(Acquire) {
+        shut_down::<T>();
+    }
+
+    let cache = inline_cache!(AtomicPtr<T>);
+
+    if let Some(cached_ptr) = unsafe { cache.load(Acquire).as_ref() } {
+        return cached_ptr;
+    };
+
+    fill_cache(cache, construct, register)
+}
+
+/// Tears down the values of `singleton_with_teardown` in the reverse order of their construction.
+///
+/// Only the first call has an effect. Panics of `Teardown::teardown` are propagated after all
+/// values were torn down. Shutting down is global and irreversible, it also applies to values
+/// constructed in a `SingletonContext`.
+///
+/// Afterwards `singleton_with_teardown` panics, but only that function checks for shutdown:
+/// `singleton_with` and `singleton` keep returning the torn-down values, and so do references
+/// obtained before.
+pub fn shutdown() {
+    SHUT_DOWN.store(true, Release);
+
+    let registered = std::mem::take(&mut *TEARDOWNS.lock().unwrap_or_else(PoisonError::into_inner));
+    let mut panic = None;
+    for value in registered.into_iter().rev() {
+        if let Err(payload) = catch_unwind(AssertUnwindSafe(|| value.teardown())) {
+            panic.get_or_insert(payload);
+        }
+    }
+    if let Some(payload) = panic {
+        resume_unwind(payload);
+    }
+}
+
+static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+static TEARDOWNS: Mutex<Vec<&'static dyn Teardown>> = Mutex::new(Vec::new());
+
+#[inline(never)]
+#[cold]
+fn shut_down<T>() -> ! {
+    panic!(
+        "generic_singleton: the singleton {} was accessed after shutdown",
+        type_name::<T>()
+    );
+}
+
+fn register<T: Teardown>(value: &'static T) {
+    let mut teardowns = TEARDOWNS.lock().unwrap_or_else(PoisonError::into_inner);
+    if SHUT_DOWN.load(Acquire) {
+        drop(teardowns);
+        value.teardown();
+        shut_down::<T>();
+    }
+    teardowns.push(value);
+
+    static AT_EXIT: Once = Once::new();
+    AT_EXIT.call_once(register_at_exit);
+}
+
+#[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+fn register_at_exit() {
+    unsafe extern "C" {
+        fn atexit(callback: extern "C" fn()) -> std::ffi::c_int;
+    }
+
+    extern "C" fn shutdown_at_exit() {
+        // The panic hook already reported any panic, and there is no caller left to propagate it to
+        let _ = catch_unwind(shutdown);
+    }
+
+    unsafe {
+        atexit(shutdown_at_exit);
+    }
+}
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+fn register_at_exit() {}
@@ -0,0 +1,46 @@
+//! `shutdown` is irreversible for the whole process, so it is tested in a test binary of its own.
+use std::{panic::catch_unwind, sync::Mutex};
+
+use generic_singleton::{Teardown, shutdown, singleton_with, singleton_with_teardown};
+
+#[test]
+fn teardown_order() {
+    static TORN_DOWN: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    struct Log;
+    struct Buffer;
+
+    impl Teardown for Log {
+        fn teardown(&self) {
+            TORN_DOWN.lock().unwrap().push("log");
+        }
+    }
+
+    impl Teardown for Buffer {
+        fn teardown(&self) {
+            TORN_DOWN.lock().unwrap().push("buffer");
+        }
+    }
+
+    // The buffer is constructed second, as its constructor needs the log
+    singleton_with_teardown(|| {
+        singleton_with_teardown(|| Log);
+        Buffer
+    });
+    let log = singleton_with_teardown(|| Log);
+
+    shutdown();
+    assert_eq!(*TORN_DOWN.lock().unwrap(), ["buffer", "log"]);
+
+    let result = catch_unwind(|| singleton_with_teardown(|| Log).teardown());
+    let message = result.unwrap_err().downcast::<String>().unwrap();
+    assert!(
+        message.ends_with("Log was accessed after shutdown"),
+        "{message}"
+    );
+    // `singleton_with` doesn't check for shutdown
+    assert!(std::ptr::eq(singleton_with(|| Log), log));
+
+    shutdown();
+    assert_eq!(TORN_DOWN.lock().unwrap().len(), 2);
+}
@@ -0,0 +1,54 @@
+//! Compares concurrent increments of unpadded and `padded` slots that belong to different keys.
+//!
+//! Run with `cargo bench -p inline_cache --bench padded`.
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+use inline_cache::type_cache;
+
+const THREADS: usize = 4;
+const ITERATIONS: usize = 10_000_000;
+
+struct Key<const N: usize>;
+
+#[inline(never)]
+fn unpadded<const N: usize>() -> &'static AtomicUsize {
+    type_cache!(AtomicUsize, Key<N>)
+}
+
+#[inline(never)]
+fn padded<const N: usize>() -> &'static AtomicUsize {
+    type_cache!(padded AtomicUsize, Key<N>)
+}
+
+fn run(slots: [fn() -> &'static AtomicUsize; THREADS]) -> Duration {
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for slot in slots {
+            scope.spawn(move || {
+                let slot = slot();
+                for _ in 0..ITERATIONS {
+                    slot.fetch_add(1, Relaxed);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let unpadded_slots = [unpadded::<0>, unpadded::<1>, unpadded::<2>, unpadded::<3>];
+    let padded_slots = [padded::<0>, padded::<1>, padded::<2>, padded::<3>];
+
+    for (name, slots) in [("unpadded", unpadded_slots), ("padded", padded_slots)] {
+        let addresses: Vec<_> = slots.iter().map(|slot| slot() as *const _).collect();
+        let elapsed = run(slots);
+        println!(
+            "{name:>8}: {:>8.2?} for {THREADS} threads x {ITERATIONS} increments, slots at {addresses:?}",
+            elapsed
+        );
+    }
+}
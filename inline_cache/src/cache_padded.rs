@@ -0,0 +1,44 @@
+use std::ops::{Deref, DerefMut};
+
+use bytemuck::Zeroable;
+
+/// Pads and aligns a value to the size of a cache line, so that it doesn't share a cache line with
+/// any other value.
+///
+/// Uses 128 bytes on targets that prefetch adjacent cache line pairs and 64 bytes elsewhere.
+#[cfg_attr(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+    ),
+    repr(C, align(128))
+)]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+    )),
+    repr(C, align(64))
+)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct CachePadded<T>(pub T);
+
+unsafe impl<T: Zeroable> Zeroable for CachePadded<T> {}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hash, Hasher},
+    marker::PhantomData,
+    panic::Location,
+    process::abort,
+    ptr::NonNull,
+    sync::{
+        RwLock,
+        atomic::{
+            AtomicPtr,
+            Ordering::{Acquire, Release},
+        },
+    },
+};
+
+use bytemuck::Zeroable;
+use inline_cache_abi::IdentityHasher;
+
+use super::{inline_cache_id, type_cache};
+
+struct CallerCache<K: ?Sized>(PhantomData<K>);
+
+struct CallerEntry<T> {
+    location: &'static Location<'static>,
+    value: T,
+}
+
+#[derive(PartialEq, Eq)]
+struct CallerKey {
+    slot: TypeId,
+    location: usize,
+}
+
+impl Hash for CallerKey {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut slot_hasher = IdentityHasher::default();
+        self.slot.hash(&mut slot_hasher);
+        let mixed =
+            (slot_hasher.finish() ^ self.location as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        state.write_u64(mixed ^ (mixed >> 32));
+    }
+}
+
+struct Ptr(NonNull<()>);
+
+unsafe impl Send for Ptr {}
+unsafe impl Sync for Ptr {}
+
+static CALLERS: RwLock<HashMap<CallerKey, Ptr, BuildHasherDefault<IdentityHasher>>> = RwLock::new(
+    HashMap::with_hasher(<BuildHasherDefault<IdentityHasher>>::new()),
+);
+
+/// The number of recent hits of a `caller_cache!` slot that are cached inline.
+const RECENT_HITS: usize = 8;
+
+#[inline(always)]
+pub fn caller_cache<T: Sync + Zeroable, K: ?Sized>(
+    location: &'static Location<'static>,
+) -> &'static T {
+    // Callers are spread over the recent hits by their location, so that a few callers taking
+    // turns don't evict each other
+    let recent_hits = type_cache::<[AtomicPtr<CallerEntry<T>>; RECENT_HITS], CallerCache<K>>();
+    let hash = (location as *const _ as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let recent_hit = &recent_hits[(hash >> 32) as usize % RECENT_HITS];
+
+    if let Some(entry) = unsafe { recent_hit.load(Acquire).as_ref() }
+        && std::ptr::eq(entry.location, location)
+    {
+        return &entry.value;
+    }
+
+    let entry = caller_entry::<T, K>(location);
+    recent_hit.store(entry as *const _ as *mut _, Release);
+    &entry.value
+}
+
+#[inline(never)]
+#[cold]
+fn caller_entry<T: Sync + Zeroable, K: ?Sized>(
+    location: &'static Location<'static>,
+) -> &'static CallerEntry<T> {
+    let key = CallerKey {
+        slot: inline_cache_id::<T, K>(),
+        location: location as *const _ as usize,
+    };
+
+    {
+        let Ok(callers) = CALLERS.read() else {
+            abort();
+        };
+        if let Some(found) = callers.get(&key) {
+            return unsafe { found.0.cast().as_ref() };
+        }
+    }
+
+    let Ok(mut callers) = CALLERS.write() else {
+        abort();
+    };
+    let found = callers.entry(key).or_insert_with(|| {
+        let entry = Box::leak(Box::new(CallerEntry {
+            location,
+            value: T::zeroed(),
+        }));
+        Ptr(NonNull::from(entry).cast())
+    });
+    unsafe { found.0.cast().as_ref() }
+}
+
+/// Returns all callers that accessed `caller_cache!(T, K)` so far, together with their slots.
+pub fn callers<T: Sync + Zeroable, K: ?Sized>() -> Vec<(&'static Location<'static>, &'static T)> {
+    let slot = inline_cache_id::<T, K>();
+
+    let Ok(callers) = CALLERS.read() else {
+        abort();
+    };
+    callers
+        .iter()
+        .filter(|(key, _)| key.slot == slot)
+        .map(|(_, found)| {
+            let entry = unsafe { found.0.cast::<CallerEntry<T>>().as_ref() };
+            (entry.location, &entry.value)
+        })
+        .collect()
+}
@@ -0,0 +1,246 @@
+//! Access to slots from C.
+//!
+//! `inline_cache!(T, export = "c_name")` defines a zero initialized static exported under the given
+//! symbol name, so that C code linked into the same program can declare and use it, and Rust code
+//! uses it through the macro. It is not a `type_cache!` slot: it is defined the same way by all
+//! backends, a `type_cache!` or `inline_cache!` slot of the same type is separate storage, and it
+//! is not registered for `snapshot`. For hosts that can't link against data symbols, e.g. when
+//! loading the library with `dlopen`, the macro also exports the function `c_name_slot` returning
+//! the address of the static. It is a single object, so the macro must not be used with generic
+//! parameters in `T`.
+//!
+//! The declarations for a C or C++ header are generated by `CHeader`, for atomics, arrays and
+//! structs declared with `c_struct!`.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::atomic::{
+        AtomicBool, AtomicI8, AtomicI16, AtomicI32, AtomicI64, AtomicIsize, AtomicU8, AtomicU16,
+        AtomicU32, AtomicU64, AtomicUsize,
+    },
+};
+
+use bytemuck::Zeroable;
+
+/// Slot types that can be exported to C.
+///
+/// Implemented for atomics and arrays of them. Use `c_struct!` for `#[repr(C)]` structs instead of
+/// implementing it by hand.
+///
+/// # Safety
+///
+/// The C declarations must describe a type with the same size, alignment and layout as `Self`.
+/// As Rust accesses slots through shared references, any field C code writes to must have
+/// interior mutability on the Rust side, e.g. be an atomic.
+pub unsafe trait CType: Sync + Zeroable {
+    /// Returns a C declaration of `declarator` with this type, e.g. `uint64_t declarator`.
+    fn c_declaration(declarator: &str) -> String;
+
+    /// Returns C definitions the declaration depends on, e.g. of a struct type.
+    fn c_definitions() -> Vec<String> {
+        vec![]
+    }
+}
+
+macro_rules! impl_c_type_for_atomics {
+    ($($Atomic:ty => $c_type:literal),* $(,)?) => {
+        $(
+            unsafe impl CType for $Atomic {
+                fn c_declaration(declarator: &str) -> String {
+                    format!(concat!("INLINE_CACHE_ATOMIC(", $c_type, ") {}"), declarator)
+                }
+            }
+        )*
+    };
+}
+
+impl_c_type_for_atomics! {
+    AtomicBool => "bool",
+    AtomicU8 => "uint8_t",
+    AtomicU16 => "uint16_t",
+    AtomicU32 => "uint32_t",
+    AtomicU64 => "uint64_t",
+    AtomicUsize => "size_t",
+    AtomicI8 => "int8_t",
+    AtomicI16 => "int16_t",
+    AtomicI32 => "int32_t",
+    AtomicI64 => "int64_t",
+    AtomicIsize => "ptrdiff_t",
+}
+
+unsafe impl<T: CType, const N: usize> CType for [T; N]
+where
+    [T; N]: Zeroable,
+{
+    fn c_declaration(declarator: &str) -> String {
+        T::c_declaration(&format!("{declarator}[{N}]"))
+    }
+
+    fn c_definitions() -> Vec<String> {
+        T::c_definitions()
+    }
+}
+
+/// Declares a `#[repr(C)]` struct that implements `CType`, so that it can be exported to C.
+///
+/// All fields must implement `CType`. The C struct has the same name and fields as the Rust one.
+///
+/// ```
+/// use std::sync::atomic::AtomicU32;
+///
+/// inline_cache::c_struct! {
+///     pub struct Limits {
+///         pub soft: AtomicU32,
+///         pub hard: [AtomicU32; 2],
+///     }
+/// }
+///
+/// let header = inline_cache::ffi::CHeader::new().slot::<Limits>("limits").to_string();
+/// assert!(header.contains("struct Limits {\n    INLINE_CACHE_ATOMIC(uint32_t) soft;\n"));
+/// assert!(header.contains("extern struct Limits limits;"));
+/// ```
+#[macro_export]
+macro_rules! c_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $Name:ident {
+            $($(#[$field_attr:meta])* $field_vis:vis $field:ident: $F:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        $vis struct $Name {
+            $($(#[$field_attr])* $field_vis $field: $F,)*
+        }
+
+        // Every field is `CType` and thus `Zeroable`
+        unsafe impl $crate::private::Zeroable for $Name {}
+
+        unsafe impl $crate::ffi::CType for $Name {
+            fn c_declaration(declarator: &str) -> ::std::string::String {
+                ::std::format!(::std::concat!("struct ", ::std::stringify!($Name), " {}"), declarator)
+            }
+
+            fn c_definitions() -> ::std::vec::Vec<::std::string::String> {
+                let mut definitions = ::std::vec::Vec::new();
+                $(definitions.extend(<$F as $crate::ffi::CType>::c_definitions());)*
+                let mut definition =
+                    ::std::string::String::from(::std::concat!("struct ", ::std::stringify!($Name), " {\n"));
+                $(
+                    definition.push_str("    ");
+                    definition.push_str(&<$F as $crate::ffi::CType>::c_declaration(::std::stringify!($field)));
+                    definition.push_str(";\n");
+                )*
+                definition.push_str("};");
+                definitions.push(definition);
+                definitions
+            }
+        }
+    };
+}
+
+/// Generates a C header declaring exported slots.
+///
+/// The header includes the headers needed by the declarations of this crate's `CType`
+/// implementations, and wraps the declarations in `extern "C"` for C++. Atomics are declared with
+/// the `INLINE_CACHE_ATOMIC` macro it defines, which is `_Atomic` in C and `std::atomic` in C++.
+/// Each slot is declared along with its `_slot` accessor function.
+#[derive(Clone, Debug, Default)]
+pub struct CHeader {
+    definitions: Vec<String>,
+    /// Declarations by slot name, sorted to keep the header stable when slots are added in any
+    /// order.
+    declarations: BTreeMap<String, String>,
+}
+
+impl CHeader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the slot exported as `inline_cache!(T, export = "name")`.
+    pub fn slot<T: CType>(mut self, name: &str) -> Self {
+        for definition in T::c_definitions() {
+            if !self.definitions.contains(&definition) {
+                self.definitions.push(definition);
+            }
+        }
+        let declaration = format!(
+            "extern {};\n{};",
+            T::c_declaration(name),
+            T::c_declaration(&format!("(*{name}_slot(void))"))
+        );
+        self.declarations.insert(name.to_owned(), declaration);
+        self
+    }
+}
+
+impl fmt::Display for CHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "/* Generated by inline_cache, do not edit. */")?;
+        writeln!(f, "#pragma once")?;
+        writeln!(f)?;
+        for include in ["stdbool.h", "stddef.h", "stdint.h"] {
+            writeln!(f, "#include <{include}>")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "#ifdef __cplusplus")?;
+        writeln!(f, "#include <atomic>")?;
+        writeln!(f, "#define INLINE_CACHE_ATOMIC(T) std::atomic<T>")?;
+        writeln!(f, "#else")?;
+        writeln!(f, "#include <stdatomic.h>")?;
+        writeln!(f, "#define INLINE_CACHE_ATOMIC(T) _Atomic(T)")?;
+        writeln!(f, "#endif")?;
+        writeln!(f)?;
+        writeln!(f, "#ifdef __cplusplus\nextern \"C\" {{\n#endif")?;
+        for definition in &self.definitions {
+            writeln!(f, "\n{definition}")?;
+        }
+        writeln!(f)?;
+        for declaration in self.declarations.values() {
+            writeln!(f, "{declaration}")?;
+        }
+        writeln!(f, "\n#ifdef __cplusplus\n}}\n#endif")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed};
+
+    use super::*;
+
+    #[test]
+    fn exported_slot() {
+        fn hits() -> &'static AtomicU64 {
+            crate::inline_cache!(AtomicU64, export = "inline_cache_test_hits")
+        }
+
+        hits().fetch_add(1, Relaxed);
+        assert_eq!(hits().load(Relaxed), 1);
+
+        unsafe extern "C" {
+            static inline_cache_test_hits: AtomicU64;
+        }
+        assert!(std::ptr::eq(hits(), &raw const inline_cache_test_hits));
+
+        unsafe extern "C" {
+            fn inline_cache_test_hits_slot() -> *const std::ffi::c_void;
+        }
+        assert!(std::ptr::eq(
+            hits(),
+            unsafe { inline_cache_test_hits_slot() }.cast()
+        ));
+
+        let header = CHeader::new()
+            .slot::<[AtomicU32; 4]>("b")
+            .slot::<AtomicU64>("a")
+            .to_string();
+        assert!(header.contains(concat!(
+            "extern INLINE_CACHE_ATOMIC(uint64_t) a;\n",
+            "INLINE_CACHE_ATOMIC(uint64_t) (*a_slot(void));\n",
+            "extern INLINE_CACHE_ATOMIC(uint32_t) b[4];\n",
+            "INLINE_CACHE_ATOMIC(uint32_t) (*b_slot(void))[4];\n",
+        )));
+    }
+}
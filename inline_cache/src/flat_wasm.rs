@@ -1,65 +1,57 @@
 use std::{
     alloc::Layout,
     any::TypeId,
-    collections::HashMap,
-    hash::BuildHasherDefault,
     process::abort,
     ptr::{NonNull, null_mut},
     sync::{
         Mutex,
         atomic::{
             AtomicPtr,
-            Ordering::{Acquire, Relaxed, Release},
+            Ordering::{Acquire, Release},
         },
     },
 };
 
-use super::identity_hasher::IdentityHasher;
-
-struct Ptr(NonNull<u8>);
-
-unsafe impl Send for Ptr {}
-unsafe impl Sync for Ptr {}
+use inline_cache_abi::{SlotOps, alloc_fallback_slot};
 
 static CACHE_BUF_INIT: cache_buf::CacheBufInit<AtomicPtr<u8>> =
     cache_buf::CacheBufInit::new(AtomicPtr::new(null_mut()));
 
 static CACHE_BUF: cache_buf::CacheBuf<AtomicPtr<u8>> = cache_buf::CacheBuf::new(&CACHE_BUF_INIT);
 
-static CACHE: Mutex<HashMap<TypeId, Ptr, BuildHasherDefault<IdentityHasher>>> = Mutex::new(
-    HashMap::with_hasher(<BuildHasherDefault<IdentityHasher>>::new()),
-);
+static FILL_LOCK: Mutex<()> = Mutex::new(());
 
 #[inline]
-pub unsafe fn type_cache(key: fn() -> TypeId, layout: Layout) -> NonNull<u8> {
+pub unsafe fn type_cache(
+    key: fn() -> TypeId,
+    layout: Layout,
+    ops: Option<SlotOps>,
+) -> NonNull<u8> {
     let ptr = CACHE_BUF.get(key as usize);
 
     unsafe {
-        let target = ptr.load(Relaxed);
+        // Acquire to make sure the zeroed slot is visible, pairs with the `Release` store of
+        // `type_cache_fallback`
+        let target = ptr.load(Acquire);
         if let Some(found) = NonNull::new(target) {
             found
         } else {
-            type_cache_fallback(key, layout)
+            type_cache_fallback(key, layout, ops)
         }
     }
 }
 
 #[inline(never)]
 #[cold]
-pub unsafe fn type_cache_fallback(key: fn() -> TypeId, layout: Layout) -> NonNull<u8> {
-    let Ok(mut cache) = CACHE.lock() else {
+pub unsafe fn type_cache_fallback(
+    key: fn() -> TypeId,
+    layout: Layout,
+    ops: Option<SlotOps>,
+) -> NonNull<u8> {
+    let Ok(_filling) = FILL_LOCK.lock() else {
         abort();
     };
-    let found = match cache.entry(key()) {
-        std::collections::hash_map::Entry::Occupied(entry) => entry.get().0,
-        std::collections::hash_map::Entry::Vacant(entry) => {
-            let Some(ptr) = NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) }) else {
-                std::alloc::handle_alloc_error(layout);
-            };
-            entry.insert(Ptr(ptr));
-            ptr
-        }
-    };
+    let found = alloc_fallback_slot(key(), layout, ops);
 
     if CACHE_BUF.len() <= key as usize {
         CACHE_BUF.grow(key as usize, |i, old| {
@@ -0,0 +1,133 @@
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    mem::MaybeUninit,
+    sync::atomic::{
+        AtomicU8,
+        Ordering::{Acquire, Relaxed, Release},
+    },
+};
+
+use bytemuck::Zeroable;
+
+use crate::private::type_cache;
+
+/// Declares statics that are separate for every instantiation of the surrounding generic items.
+///
+/// Can be used inside the body of generic functions and methods. Each `NAME` becomes a local
+/// `&'static Ty`, initialized with the const initializer on first use.
+///
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+/// fn next_id<T>() -> usize {
+///     inline_cache::generic_static! {
+///         static NEXT: AtomicUsize = AtomicUsize::new(1);
+///     }
+///     NEXT.fetch_add(1, Relaxed)
+/// }
+/// assert_eq!(next_id::<u8>(), 1);
+/// assert_eq!(next_id::<u8>(), 2);
+/// assert_eq!(next_id::<u16>(), 1);
+/// ```
+#[macro_export]
+macro_rules! generic_static {
+    ($($(#[$attr:meta])* static $name:ident: $T:ty = $init:expr;)*) => {
+        $(
+            #[allow(non_snake_case, unused_doc_comments)]
+            $(#[$attr])*
+            let $name: &'static $T = {
+                let key = $crate::__call_site_key!();
+                $crate::private::generic_static::<$T, _>(&key, || -> $T { const { $init } })
+            };
+        )*
+    };
+}
+
+const UNINIT: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+
+struct GenericStatic<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T> Zeroable for GenericStatic<T> {}
+unsafe impl<T: Sync> Sync for GenericStatic<T> {}
+
+impl<T: Sync> GenericStatic<T> {
+    #[inline(always)]
+    fn get(&'static self, init: impl FnOnce() -> T) -> &'static T {
+        if self.state.load(Acquire) != READY {
+            self.init(init);
+        }
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn init(&self, init: impl FnOnce() -> T) {
+        loop {
+            match self
+                .state
+                .compare_exchange_weak(UNINIT, INITIALIZING, Acquire, Acquire)
+            {
+                Ok(_) => {
+                    unsafe { (*self.value.get()).write(init()) };
+                    self.state.store(READY, Release);
+                    return;
+                }
+                Err(READY) => return,
+                Err(_) => spin_loop(),
+            }
+            // Initializers are constants, so the initializing thread will be done in a moment
+            while self.state.load(Relaxed) == INITIALIZING {
+                spin_loop();
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub fn generic_static<T: Sync, K>(_key: &K, init: impl FnOnce() -> T) -> &'static T {
+    type_cache::<GenericStatic<T>, K>().get(init)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    #[test]
+    fn per_instantiation() {
+        fn counters<T>(_: T) -> (usize, usize, &'static str) {
+            generic_static! {
+                static A: AtomicUsize = AtomicUsize::new(10);
+                /// Starts at a different value
+                static B: AtomicUsize = AtomicUsize::new(20);
+                static NAME: &str = "name";
+            }
+            (A.fetch_add(1, Relaxed), B.fetch_add(2, Relaxed), NAME)
+        }
+
+        assert_eq!(counters(0u8), (10, 20, "name"));
+        assert_eq!(counters(0u8), (11, 22, "name"));
+        assert_eq!(counters(0u16), (10, 20, "name"));
+        assert_eq!(counters(0u8), (12, 24, "name"));
+
+        struct Wrapper<T>(T);
+
+        impl<T> Wrapper<T> {
+            fn count(&self) -> usize {
+                generic_static! {
+                    static COUNT: AtomicUsize = AtomicUsize::new(0);
+                }
+                COUNT.fetch_add(1, Relaxed)
+            }
+        }
+
+        assert_eq!(Wrapper(1u8).count(), 0);
+        assert_eq!(Wrapper(2u8).count(), 1);
+        assert_eq!(Wrapper(()).count(), 0);
+    }
+}
@@ -1,70 +1,605 @@
-use std::{any::TypeId, marker::PhantomData};
+use std::marker::PhantomData;
 
-use bytemuck::Zeroable;
 use cfg_if::cfg_if;
 
+pub use cache_padded::CachePadded;
+pub use percpu::PerCpu;
+pub use private::callers;
+pub use snapshot::{RestoreError, SlotKey, Snapshot, SnapshotSlot, restore, slot_names, snapshot};
+
+pub use inline_cache_abi::HEAP_THRESHOLD;
+
+mod cache_padded;
+pub mod ffi;
+mod generic_static;
+pub mod metrics;
+mod percpu;
+pub mod profile;
+pub mod rate_limit;
+mod registry;
+mod snapshot;
+
 #[macro_export]
 macro_rules! type_cache {
+    (padded _, $key:literal) => {
+        &$crate::private::shared_slot::<$crate::CachePadded<_>, $crate::__str_key!($key)>(None).0
+    };
+    (padded _, const $N:expr) => {
+        $crate::private::type_cache_padded::<_, $crate::private::ConstKey<{ $N }>>()
+    };
+    (padded _, $K:ty) => {
+        $crate::private::type_cache_padded::<_, $K>()
+    };
+    (padded _) => {
+        $crate::private::type_cache_padded::<_, ()>()
+    };
+    (padded $T:ty, $key:literal) => {
+        &$crate::__probe_type_cache!(shared $crate::CachePadded<$T>, $crate::__str_key!($key)).0
+    };
+    (padded $T:ty, const $N:expr) => {
+        &$crate::__probe_type_cache!(
+            $crate::CachePadded<$T>,
+            $crate::private::ConstKey<{ $N }>)
+        .0
+    };
+    (padded $T:ty, $K:ty) => {
+        &$crate::__probe_type_cache!(
+            $crate::CachePadded<$T>,
+            $K)
+        .0
+    };
+    (padded $T:ty) => {
+        &$crate::__probe_type_cache!(
+            $crate::CachePadded<$T>,
+            ())
+        .0
+    };
+    (_, $key:literal) => {
+        $crate::private::shared_slot::<_, $crate::__str_key!($key)>(None)
+    };
+    (_, const $N:expr) => {
+        $crate::private::type_cache::<_, $crate::private::ConstKey<{ $N }>>()
+    };
+    (_, $K:ty) => {
+        $crate::private::type_cache::<_, $K>()
+    };
+    (_) => {
+        $crate::private::type_cache::<_, ()>()
+    };
+    ($T:ty, $key:literal) => {
+        $crate::__probe_type_cache!(shared $T, $crate::__str_key!($key))
+    };
+    ($T:ty, const $N:expr) => {
+        $crate::__probe_type_cache!(
+            $T,
+            $crate::private::ConstKey<{ $N }>)
+    };
     ($T:ty, $K:ty) => {
-        $crate::private::type_cache::<$T, $K>()
+        $crate::__probe_type_cache!($T, $K)
     };
     ($T:ty) => {
-        $crate::private::type_cache::<$T, ()>()
+        $crate::__probe_type_cache!($T, ())
     };
 }
 
+/// Uses `private::type_cache_snapshot` for slot types implementing `SnapshotSlot` and
+/// `private::type_cache` for everything else, see `snapshot`.
+#[doc(hidden)]
 #[macro_export]
-macro_rules! inline_cache {
-    ($T:ty, $K:ty) => {{
-        struct InlineCache<K: ?Sized>(PhantomData<K>);
-        $crate::private::type_cache::<$T, InlineCache<$K>>()
+macro_rules! __probe_type_cache {
+    (shared $T:ty, $K:ty) => {{
+        #[allow(unused_imports)]
+        use $crate::private::{AnySlotProbe as _, SnapshotSlotProbe as _};
+        (&&$crate::private::SlotProbe::<$T, $K>::new()).shared_slot()
     }};
-    ($T:ty) => {{
-        struct InlineCache;
-        $crate::private::type_cache::<$T, InlineCache>()
+    ($T:ty, $K:ty) => {{
+        #[allow(unused_imports)]
+        use $crate::private::{AnySlotProbe as _, SnapshotSlotProbe as _};
+        (&&$crate::private::SlotProbe::<$T, $K>::new()).type_cache()
     }};
 }
 
-trait PhantomAny {
-    fn inner_type_id(&self) -> std::any::TypeId
-    where
-        Self: 'static;
+/// Evaluates to a closure whose type serves as a slot key for the macro call site that expands this.
+///
+/// Closure types are distinct for every closure expression and every instantiation of the
+/// surrounding generic items, so the key is separate per call site and per instantiation, and its
+/// `type_name` is the path of the surrounding function including its generic arguments, followed by
+/// `::{{closure}}`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __call_site_key {
+    () => {
+        || ()
+    };
 }
 
-impl<T: ?Sized> PhantomAny for PhantomData<T> {
-    #[inline(always)]
-    fn inner_type_id(&self) -> std::any::TypeId
-    where
-        Self: 'static,
-    {
-        std::any::TypeId::of::<Self>()
-    }
+/// Defines the slot of `{symbol}` as a weak symbol in a COMDAT group of its own, unless it was
+/// already defined in this object file. Unlike a `.comm` symbol, this can be aliased by a
+/// debugger name. The slot is placed in `.bss.{symbol}_SLOT` or the given section, which has the
+/// given section type.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __elf_slot_definition {
+    () => {
+        $crate::__elf_slot_definition!(".bss.{symbol}_SLOT", "%nobits")
+    };
+    ($section:literal, $type:literal) => {
+        concat!(
+            ".ifndef {symbol}_SLOT\n",
+            ".pushsection ",
+            $section,
+            ",\"awG\",",
+            $type,
+            ",{symbol}_SLOT,comdat\n",
+            ".weak {symbol}_SLOT\n",
+            ".type {symbol}_SLOT, %object\n",
+            ".size {symbol}_SLOT, {size}\n",
+            ".balign {align}\n",
+            ".set {symbol}_SLOT, .\n",
+            ".zero {size}\n",
+            ".popsection\n",
+            ".endif",
+        )
+    };
 }
 
-#[inline(always)]
-fn erased_type_id<T>() -> TypeId {
-    let phantom: PhantomData<T> = PhantomData;
-    let dyn_phantom: &dyn PhantomAny = &phantom;
-    let dyn_static_phantom: &(dyn PhantomAny + 'static) =
-        unsafe { &*(dyn_phantom as *const _ as *const _) };
-    PhantomAny::inner_type_id(dyn_static_phantom)
+/// Names the slot of `{symbol}`, unless it was already named in this object file.
+///
+/// Defines `{symbol}.inline_cache` as a local alias of the slot, which demangles to
+/// `slot_id::<T, K>` including the type arguments with the v0 symbol mangling scheme, and adds an
+/// entry for the slot to the `inline_cache_slot_names` section, whose `{describe}` ops give the
+/// `type_name`s of `T` and `K` independently of the mangling scheme, see `slot_names`. Each entry
+/// is in its own COMDAT group, so that the linker keeps only one entry per slot.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __elf_slot_name {
+    () => {
+        concat!(
+            ".ifndef {symbol}.inline_cache\n",
+            ".set {symbol}.inline_cache, {symbol}_SLOT\n",
+            ".type {symbol}.inline_cache, %object\n",
+            ".size {symbol}.inline_cache, {size}\n",
+            ".pushsection inline_cache_slot_names,\"awGR\",%progbits,{symbol}_NAME,comdat\n",
+            ".weak {symbol}_NAME\n",
+            ".hidden {symbol}_NAME\n",
+            ".p2align 3\n",
+            ".set {symbol}_NAME, .\n",
+            ".8byte {symbol}_SLOT\n",
+            ".8byte {describe}\n",
+            ".popsection\n",
+            ".endif",
+        )
+    };
 }
 
-struct InlineCache<T: Sync + Zeroable, K: ?Sized>(PhantomData<T>, PhantomData<K>);
+/// Loads the address of the slot of `{symbol}` into `{slot}`, for the ELF backends and
+/// `__section_slot!`.
+#[cfg(target_arch = "x86_64")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __elf_slot_address {
+    () => {
+        "mov {slot}, [rip + {symbol}_SLOT@GOTPCREL]"
+    };
+}
 
-fn inline_cache_id<T: Sync + Zeroable, K: ?Sized>() -> TypeId {
-    erased_type_id::<InlineCache<T, K>>()
+/// Loads the address of the slot of `{symbol}` into `{slot}`, for the ELF backends and
+/// `__section_slot!`.
+#[cfg(target_arch = "aarch64")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __elf_slot_address {
+    () => {
+        concat!(
+            "adrp {slot}, :got:{symbol}_SLOT\n",
+            "ldr {slot}, [{slot}, :got_lo12:{symbol}_SLOT]",
+        )
+    };
+}
+
+/// The slot of `inline_cache!(T, K, section = "...")`.
+///
+/// The slot is defined in the given section by the code using it, which is never shared with
+/// other macro invocations as `K` is local to the call site. Every object file referencing the
+/// slot thus defines it in the same section, and the COMDAT group keeps a single definition. Slot
+/// types that aren't stored inline, and backends other than ELF, ignore the section.
+///
+/// COMDAT sections need an explicit section type. It is the one the assembler infers from the
+/// section name, as for `#[link_section]` statics, so that the slots can share a section with
+/// them: `.bss` sections are `%nobits`, all others are `%progbits`.
+#[cfg(all(
+    not(any(feature = "force_fallback_impl", miri)),
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __section_slot {
+    ($T:ty, $K:ty, $section:literal) => {
+        if const { !$crate::private::is_inline_slot::<$T>() } {
+            $crate::private::type_cache::<$T, $K>()
+        } else if const { $crate::private::is_bss_section($section) } {
+            $crate::__section_slot!(@define $T, $K, $section, "%nobits")
+        } else {
+            $crate::__section_slot!(@define $T, $K, $section, "%progbits")
+        }
+    };
+    (@define $T:ty, $K:ty, $section:literal, $type:literal) => {
+        unsafe {
+            let slot_ptr: *const $T;
+            ::core::arch::asm!(
+                $crate::__elf_slot_definition!($section, $type),
+                $crate::__elf_slot_name!(),
+                $crate::__elf_slot_address!(),
+                slot = out(reg) slot_ptr,
+                size = const ::std::mem::size_of::<$T>(),
+                align = const ::std::mem::align_of::<$T>(),
+                symbol = sym $crate::private::slot_id::<$T, $K>,
+                describe = sym $crate::private::describe_slot::<$T, $K>,
+                options(pure, nomem, preserves_flags, nostack),
+            );
+            &*slot_ptr
+        }
+    };
+}
+
+#[cfg(not(all(
+    not(any(feature = "force_fallback_impl", miri)),
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __section_slot {
+    ($T:ty, $K:ty, $section:literal) => {
+        $crate::private::type_cache::<$T, $K>()
+    };
 }
 
+/// The key type of `type_cache!(T, "key")`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __str_key {
+    ($key:literal) => {
+        $crate::private::StrKey<
+            { $key.len() },
+            { $crate::private::str_key_chunk($key, 0) },
+            { $crate::private::str_key_chunk($key, 1) },
+            { $crate::private::str_key_chunk($key, 2) },
+            { $crate::private::str_key_chunk($key, 3) },
+        >
+    };
+}
+
+/// A slot per caller of the surrounding `#[track_caller]` function, keyed by `K` in addition.
+///
+/// The slots are kept in a map keyed by `Location::caller()`, and the most recent hits of a few
+/// callers are cached inline, so repeated calls from those callers don't lock the map. The callers
+/// of `caller_cache!(T, K)` can be listed with `callers::<T, K>()`. Without `K`, the key is local
+/// to the call site, like for `inline_cache!`, so the callers of different functions never share
+/// a slot, but they can't be listed.
+#[macro_export]
+macro_rules! caller_cache {
+    ($T:ty, $K:ty) => {
+        $crate::private::caller_cache::<$T, $K>(::std::panic::Location::caller())
+    };
+    ($T:ty) => {{
+        struct CallerCache;
+        $crate::private::caller_cache::<$T, CallerCache>(::std::panic::Location::caller())
+    }};
+}
+
+/// A slot unique to the call site, keyed by `K` in addition.
+///
+/// The slot is registered for `snapshot` under the same conditions as `type_cache!` slots, see
+/// `SnapshotSlot`, so the `_` forms never are.
+///
+/// With `section = "name"`, the slot is placed in the given linker section on the ELF backends,
+/// e.g. to keep hot slots on the same pages. A section named like a C identifier also gets
+/// `__start_name` and `__stop_name` symbols from the linker. Slots in a custom section are never
+/// registered for `snapshot`, whatever their type, on all backends.
+///
+/// With `export = "c_name"`, the macro evaluates to a plain static exported under that symbol name
+/// instead of a slot, along with a `c_name_slot` function returning its address, see `ffi`.
+#[macro_export]
+macro_rules! inline_cache {
+    ($T:ty, export = $name:literal $(,)?) => {{
+        #[unsafe(export_name = $name)]
+        static EXPORTED: $T = $crate::private::exported_static::<$T>();
+        #[unsafe(export_name = ::std::concat!($name, "_slot"))]
+        extern "C" fn exported_slot() -> *const ::std::ffi::c_void {
+            (&raw const EXPORTED).cast()
+        }
+        &EXPORTED
+    }};
+    (padded $T:ty, $K:ty, section = $section:literal $(,)?) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        &$crate::__section_slot!($crate::CachePadded<$T>, InlineCache<$K>, $section).0
+    }};
+    (padded $T:ty, section = $section:literal $(,)?) => {{
+        struct InlineCache;
+        &$crate::__section_slot!($crate::CachePadded<$T>, InlineCache, $section).0
+    }};
+    ($T:ty, $K:ty, section = $section:literal $(,)?) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        $crate::__section_slot!($T, InlineCache<$K>, $section)
+    }};
+    ($T:ty, section = $section:literal $(,)?) => {{
+        struct InlineCache;
+        $crate::__section_slot!($T, InlineCache, $section)
+    }};
+    (padded _, $K:ty) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        $crate::private::type_cache_padded::<_, InlineCache<$K>>()
+    }};
+    (padded _) => {{
+        struct InlineCache;
+        $crate::private::type_cache_padded::<_, InlineCache>()
+    }};
+    (padded $T:ty, $K:ty) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        &$crate::__probe_type_cache!($crate::CachePadded<$T>, InlineCache<$K>).0
+    }};
+    (padded $T:ty) => {{
+        struct InlineCache;
+        &$crate::__probe_type_cache!($crate::CachePadded<$T>, InlineCache).0
+    }};
+    (_, $K:ty) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        $crate::private::type_cache::<_, InlineCache<$K>>()
+    }};
+    (_) => {{
+        struct InlineCache;
+        $crate::private::type_cache::<_, InlineCache>()
+    }};
+    ($T:ty, $K:ty) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        $crate::__probe_type_cache!($T, InlineCache<$K>)
+    }};
+    ($T:ty) => {{
+        struct InlineCache;
+        $crate::__probe_type_cache!($T, InlineCache)
+    }};
+}
+
+// Slots are named by the `inline_cache_abi` crate, so that they are shared with other versions of
+// this crate that use the same slot ABI.
+use inline_cache_abi::slot_id as inline_cache_id;
+
 #[path = "."]
 #[doc(hidden)]
 pub mod private {
+    use std::{
+        alloc::Layout,
+        ptr::{NonNull, null_mut},
+        sync::atomic::{
+            AtomicPtr,
+            Ordering::{AcqRel, Acquire, Release},
+        },
+    };
+
+    use inline_cache_abi::{
+        HeapSlot, SharedSlot, SlotEntry, SlotOps, alloc_fallback_slot, fallback_slot,
+        register_runtime_slot,
+    };
+
     use super::*;
+    use crate::snapshot::slot_ops;
+
+    mod caller_cache;
+
+    pub use super::generic_static::generic_static;
+    pub use crate::snapshot::describe_slot;
+    pub use bytemuck::Zeroable;
+    pub use caller_cache::{caller_cache, callers};
+    pub use inline_cache_abi::{ConstKey, StrKey, slot_id, str_key_chunk};
+
+    /// Whether the slots of `T` are stored inline, i.e. have a slot symbol.
+    pub const fn is_inline_slot<T>() -> bool {
+        0 < std::mem::size_of::<T>() && std::mem::size_of::<T>() <= HEAP_THRESHOLD
+    }
+
+    /// Whether the assembler infers the `%nobits` type for the section `name`, see
+    /// `__section_slot!`.
+    pub const fn is_bss_section(name: &str) -> bool {
+        const fn starts_with(name: &[u8], prefix: &[u8]) -> bool {
+            if name.len() < prefix.len() {
+                return false;
+            }
+            let mut i = 0;
+            while i < prefix.len() {
+                if name[i] != prefix[i] {
+                    return false;
+                }
+                i += 1;
+            }
+            true
+        }
+
+        let name = name.as_bytes();
+        matches!(name, b".bss" | b".sbss")
+            || starts_with(name, b".bss.")
+            || starts_with(name, b".sbss.")
+    }
+
+    #[inline(always)]
+    pub fn type_cache<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
+        // Using inline const blocks makes sure the untaken branches are not even instantiated, so
+        // zero-sized types don't emit a slot symbol and huge types don't bloat `.bss`.
+        if const { std::mem::size_of::<T>() == 0 } {
+            unsafe { NonNull::dangling().as_ref() }
+        } else if const { std::mem::size_of::<T>() > HEAP_THRESHOLD } {
+            heap_slot::<T, K>(None)
+        } else {
+            slot::<T, K>()
+        }
+    }
+
+    /// Like `type_cache`, but also registers the slot for `snapshot` and `restore`.
+    #[inline(always)]
+    pub fn type_cache_snapshot<T: SnapshotSlot, K: ?Sized>() -> &'static T {
+        if const { std::mem::size_of::<T>() == 0 } {
+            unsafe { NonNull::dangling().as_ref() }
+        } else if const { std::mem::size_of::<T>() > HEAP_THRESHOLD } {
+            heap_slot::<T, K>(Some(slot_ops::<T, K>))
+        } else {
+            registered_slot::<T, K>()
+        }
+    }
+
+    pub struct SlotProbe<T, K: ?Sized>(PhantomData<T>, PhantomData<K>);
+
+    impl<T, K: ?Sized> SlotProbe<T, K> {
+        #[allow(clippy::new_without_default)]
+        #[inline(always)]
+        pub const fn new() -> Self {
+            Self(PhantomData, PhantomData)
+        }
+    }
+
+    // Autoref based specialization: `(&&probe).type_cache()` prefers the `SnapshotSlotProbe`
+    // implementation for `&SlotProbe` over the `AnySlotProbe` implementation for `SlotProbe`, but
+    // only when the slot type is known to implement `SnapshotSlot`.
+    pub trait SnapshotSlotProbe<T> {
+        fn type_cache(&self) -> &'static T;
+
+        fn shared_slot(&self) -> &'static T;
+    }
+
+    impl<T: SnapshotSlot, K: ?Sized> SnapshotSlotProbe<T> for &SlotProbe<T, K> {
+        #[inline(always)]
+        fn type_cache(&self) -> &'static T {
+            type_cache_snapshot::<T, K>()
+        }
+
+        #[inline(always)]
+        fn shared_slot(&self) -> &'static T {
+            shared_slot::<T, K>(Some(slot_ops::<T, K>))
+        }
+    }
+
+    pub trait AnySlotProbe<T> {
+        fn type_cache(&self) -> &'static T;
+
+        fn shared_slot(&self) -> &'static T;
+    }
+
+    impl<T: Sync + Zeroable, K: ?Sized> AnySlotProbe<T> for SlotProbe<T, K> {
+        #[inline(always)]
+        fn type_cache(&self) -> &'static T {
+            type_cache::<T, K>()
+        }
+
+        #[inline(always)]
+        fn shared_slot(&self) -> &'static T {
+            shared_slot::<T, K>(None)
+        }
+    }
+
+    /// Like `type_cache`, but for slots that live in the fallback slot map on all implementations.
+    ///
+    /// The symbol naming a slot depends on the crate instantiating it, so independent crates that
+    /// use the same key without being generic over it would get different slots. The `TypeId` of
+    /// the slot is the same everywhere though, so these slots are looked up by it once and then
+    /// cached in an inline slot.
+    #[inline(always)]
+    pub fn shared_slot<T: Sync + Zeroable, K: ?Sized>(ops: Option<SlotOps>) -> &'static T {
+        if const { std::mem::size_of::<T>() == 0 } {
+            return unsafe { NonNull::dangling().as_ref() };
+        }
+        let cached = slot::<AtomicPtr<T>, SharedSlot<K>>();
+        if let Some(found) = unsafe { cached.load(Acquire).as_ref() } {
+            return found;
+        }
+        fill_shared_slot::<T, K>(cached, ops)
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn fill_shared_slot<T: Sync + Zeroable, K: ?Sized>(
+        cached: &AtomicPtr<T>,
+        ops: Option<SlotOps>,
+    ) -> &'static T {
+        let id = inline_cache_id::<T, K>();
+        let found = fallback_slot(id)
+            .unwrap_or_else(|| alloc_fallback_slot(id, Layout::new::<T>(), ops))
+            .cast::<T>();
+        cached.store(found.as_ptr(), Release);
+        unsafe { found.as_ref() }
+    }
+
+    #[inline(always)]
+    fn heap_slot<T: Sync + Zeroable, K: ?Sized>(ops: Option<SlotOps>) -> &'static T {
+        let heap_ptr = slot::<AtomicPtr<T>, HeapSlot<K>>();
+        if let Some(found) = unsafe { heap_ptr.load(Acquire).as_ref() } {
+            return found;
+        }
+        alloc_heap_slot(heap_ptr, ops)
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn alloc_heap_slot<T: Sync + Zeroable>(
+        heap_ptr: &AtomicPtr<T>,
+        ops: Option<SlotOps>,
+    ) -> &'static T {
+        let layout = Layout::new::<T>();
+        let Some(ptr) = NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) }) else {
+            std::alloc::handle_alloc_error(layout);
+        };
+
+        match heap_ptr.compare_exchange(null_mut(), ptr.cast().as_ptr(), AcqRel, Acquire) {
+            Ok(_) => {
+                if let Some(ops) = ops {
+                    register_runtime_slot(ptr, ops);
+                }
+                unsafe { ptr.cast().as_ref() }
+            }
+            Err(existing) => {
+                unsafe { std::alloc::dealloc(ptr.as_ptr(), layout) };
+                unsafe { &*existing }
+            }
+        }
+    }
+
+    /// The initial value of the static exported by `inline_cache!(T, export = "name")`.
+    pub const fn exported_static<T: ffi::CType>() -> T {
+        unsafe { std::mem::zeroed() }
+    }
+
+    #[inline(always)]
+    pub fn type_cache_padded<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
+        &type_cache::<CachePadded<T>, K>().0
+    }
 
     macro_rules! type_cache_impl {
-        (align = $align:ident $(, $ops:literal)* $(,)? ) => {
+        // On ELF, slots are weak definitions in COMDAT groups, so that `__elf_slot_name!` can alias
+        // them. Other object formats use common symbols.
+        (align = $align:ident, object = elf $(, $ops:expr)* $(,)? ) => {
+            #[inline(always)]
+            fn slot<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
+                unsafe {
+                    let slot_ptr: *mut T;
+                    core::arch::asm!(
+                        crate::__elf_slot_definition!(),
+                        crate::__elf_slot_name!(),
+                        $($ops,)*
+                        slot = out(reg) slot_ptr,
+                        size = const std::mem::size_of::<T>(),
+                        align = const type_cache_impl!(@align, $align, T),
+                        symbol = sym inline_cache_id::<T, K>,
+                        describe = sym describe_slot::<T, K>,
+                        options(pure, nomem, preserves_flags, nostack),
+                    );
+                    &*slot_ptr
+                }
+            }
+
+            type_cache_impl!(@registry elf $(, $ops)*);
+        };
+        (align = $align:ident, object = other $(, $ops:expr)* $(,)? ) => {
             #[inline(always)]
-            pub fn type_cache<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
+            fn slot<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
                 unsafe {
                     let slot_ptr: *mut T;
                     core::arch::asm!(
@@ -79,6 +614,8 @@ pub mod private {
                     &*slot_ptr
                 }
             }
+
+            type_cache_impl!(@registry other $(, $ops)*);
         };
         (@align, bytes, $T:ty) => {
             std::mem::align_of::<$T>()
@@ -86,17 +623,124 @@ pub mod private {
         (@align, shift, $T:ty) => {
             std::mem::align_of::<$T>().trailing_zeros()
         };
+        // Every registered slot gets a `SlotEntry` in the `inline_cache_slots` section. Each entry
+        // is in its own COMDAT group, so that the linker keeps only one entry per slot, and the
+        // `.ifndef` skips the entry when the same slot was already used in this object file.
+        (@registry elf $(, $ops:expr)*) => {
+            core::arch::global_asm!(
+                ".pushsection inline_cache_slots,\"awR\",%progbits",
+                ".p2align 3",
+                ".8byte 0",
+                ".8byte 0",
+                ".popsection",
+                ".pushsection inline_cache_slot_names,\"awR\",%progbits",
+                ".p2align 3",
+                ".8byte 0",
+                ".8byte 0",
+                ".popsection",
+            );
+
+            unsafe extern "C" {
+                static __start_inline_cache_slots: [u64; 0];
+                static __stop_inline_cache_slots: [u64; 0];
+                static __start_inline_cache_slot_names: [u64; 0];
+                static __stop_inline_cache_slot_names: [u64; 0];
+            }
+
+            pub(crate) fn linker_slots() -> &'static [SlotEntry] {
+                unsafe {
+                    let start = (&raw const __start_inline_cache_slots).cast::<SlotEntry>();
+                    let stop = (&raw const __stop_inline_cache_slots).cast::<SlotEntry>();
+                    std::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+                }
+            }
+
+            pub(crate) fn linker_slot_names() -> &'static [SlotEntry] {
+                unsafe {
+                    let start = (&raw const __start_inline_cache_slot_names).cast::<SlotEntry>();
+                    let stop = (&raw const __stop_inline_cache_slot_names).cast::<SlotEntry>();
+                    std::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+                }
+            }
+
+            #[inline(always)]
+            fn registered_slot<T: SnapshotSlot, K: ?Sized>() -> &'static T {
+                unsafe {
+                    let slot_ptr: *mut T;
+                    core::arch::asm!(
+                        crate::__elf_slot_definition!(),
+                        crate::__elf_slot_name!(),
+                        ".ifndef {symbol}_REG",
+                        ".pushsection inline_cache_slots,\"awGR\",%progbits,{symbol}_REG,comdat",
+                        ".weak {symbol}_REG",
+                        ".hidden {symbol}_REG",
+                        ".p2align 3",
+                        ".set {symbol}_REG, .",
+                        ".8byte {symbol}_SLOT",
+                        ".8byte {slot_ops}",
+                        ".popsection",
+                        ".endif",
+                        $($ops,)*
+                        slot = out(reg) slot_ptr,
+                        size = const std::mem::size_of::<T>(),
+                        align = const type_cache_impl!(@align, bytes, T),
+                        symbol = sym inline_cache_id::<T, K>,
+                        slot_ops = sym slot_ops::<T, K>,
+                        describe = sym describe_slot::<T, K>,
+                        options(pure, nomem, preserves_flags, nostack),
+                    );
+                    &*slot_ptr
+                }
+            }
+        };
+        (@registry other $(, $ops:expr)*) => {
+            pub(crate) fn linker_slots() -> &'static [SlotEntry] {
+                &[]
+            }
+
+            pub(crate) fn linker_slot_names() -> &'static [SlotEntry] {
+                &[]
+            }
+
+            #[inline(always)]
+            fn registered_slot<T: SnapshotSlot, K: ?Sized>() -> &'static T {
+                slot::<T, K>()
+            }
+        };
         (mod $fallback:ident $(; mod $mod:ident)* $(;)?) => {
             mod $fallback;
             $(
                 mod $mod;
             )*
 
-            pub fn type_cache<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
+            #[inline(always)]
+            fn slot<T: Sync + Zeroable, K: ?Sized>() -> &'static T {
+                unsafe {
+                    $fallback::type_cache(
+                        inline_cache_id::<T, K>,
+                        std::alloc::Layout::new::<T>(),
+                        None,
+                    )
+                    .cast()
+                    .as_ref()
+                }
+            }
+
+            pub(crate) fn linker_slots() -> &'static [SlotEntry] {
+                &[]
+            }
+
+            pub(crate) fn linker_slot_names() -> &'static [SlotEntry] {
+                &[]
+            }
+
+            #[inline(always)]
+            fn registered_slot<T: SnapshotSlot, K: ?Sized>() -> &'static T {
                 unsafe {
                     $fallback::type_cache(
                         inline_cache_id::<T, K>,
                         std::alloc::Layout::new::<T>(),
+                        Some(slot_ops::<T, K>),
                     )
                     .cast()
                     .as_ref()
@@ -109,44 +753,45 @@ pub mod private {
         if #[cfg(any(feature = "force_fallback_impl", miri))] {
             type_cache_impl! {
                 mod fallback_rwlock;
-                mod identity_hasher;
-            }
-        } else if #[cfg(
-            all(
-                target_arch = "x86_64",
-                any(target_os = "linux", target_os = "macos"),
-            )
-        )] {
+            }
+        } else if #[cfg(all(target_arch = "x86_64", target_os = "linux"))] {
             type_cache_impl! {
                 align = bytes,
+                object = elf,
+                crate::__elf_slot_address!(),
+            }
+        } else if #[cfg(all(target_arch = "x86_64", target_os = "macos"))] {
+            type_cache_impl! {
+                align = bytes,
+                object = other,
                 "mov {slot}, [rip + {symbol}_SLOT@GOTPCREL]",
             }
         } else if #[cfg(all(target_arch = "x86_64", target_os = "windows"))] {
             type_cache_impl! {
                 align = shift,
+                object = other,
                 "lea {slot}, [rip + {symbol}_SLOT]",
             }
         } else if #[cfg(all(target_arch = "aarch64", target_os = "linux"))] {
             type_cache_impl! {
                 align = bytes,
-                "adrp {slot}, :got:{symbol}_SLOT",
-                "ldr {slot}, [{slot}, :got_lo12:{symbol}_SLOT]",
+                object = elf,
+                crate::__elf_slot_address!(),
             }
         } else if #[cfg(all(target_arch = "aarch64", target_os = "macos"))] {
             type_cache_impl! {
                 align = bytes,
+                object = other,
                 "adrp {slot}, {symbol}_SLOT@GOTPAGE",
                 "ldr {slot}, [{slot}, {symbol}_SLOT@GOTPAGEOFF]",
             }
         } else if #[cfg(any(target_arch = "wasm32", target_arch = "wasm64"))] {
             type_cache_impl! {
                 mod flat_wasm;
-                mod identity_hasher;
             }
         } else {
             type_cache_impl! {
                 mod fallback_rwlock;
-                mod identity_hasher;
             }
         }
     }
@@ -156,6 +801,8 @@ pub mod private {
 mod tests {
     use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 
+    use bytemuck::Zeroable;
+
     use super::*;
 
     #[test]
@@ -205,6 +852,287 @@ mod tests {
         step!(b(), 2, 3);
     }
 
+    #[test]
+    fn padded() {
+        struct A;
+        struct B;
+
+        let a: &'static AtomicUsize = type_cache!(padded AtomicUsize, A);
+        let b: &'static AtomicUsize = inline_cache!(padded _, B);
+        let (a, b) = (a as *const _ as usize, b as *const _ as usize);
+        assert_eq!(a % std::mem::align_of::<CachePadded<AtomicUsize>>(), 0);
+        assert_eq!(b % std::mem::align_of::<CachePadded<AtomicUsize>>(), 0);
+        assert!(a.abs_diff(b) >= std::mem::size_of::<CachePadded<AtomicUsize>>());
+
+        assert_eq!(type_cache!(padded AtomicUsize, A).fetch_add(1, Relaxed), 0);
+        assert_eq!(type_cache!(padded AtomicUsize, A).fetch_add(1, Relaxed), 1);
+        assert_eq!(type_cache!(AtomicUsize, A).load(Relaxed), 0);
+    }
+
+    #[test]
+    fn zero_sized() {
+        #[derive(Clone, Copy)]
+        #[repr(align(16))]
+        struct Zst;
+        unsafe impl Zeroable for Zst {}
+
+        let a: &'static Zst = type_cache!(Zst);
+        let b: &'static Zst = inline_cache!(Zst);
+        assert_eq!(a as *const Zst as usize % 16, 0);
+        assert_eq!(b as *const Zst as usize % 16, 0);
+        let _: &'static () = inline_cache!(());
+    }
+
+    #[test]
+    fn heap_backed() {
+        struct A;
+        struct B;
+
+        struct Huge([AtomicUsize; 2 * HEAP_THRESHOLD / std::mem::size_of::<AtomicUsize>()]);
+        unsafe impl Zeroable for Huge {}
+
+        let a = &type_cache!(Huge, A).0;
+        assert!(a.iter().all(|x| x.load(Relaxed) == 0));
+        a[0].fetch_add(1, Relaxed);
+        a[a.len() - 1].fetch_add(2, Relaxed);
+
+        let a_again = &type_cache!(Huge, A).0;
+        assert!(std::ptr::eq(a, a_again));
+        assert_eq!(a_again[0].load(Relaxed), 1);
+        assert_eq!(a_again[a.len() - 1].load(Relaxed), 2);
+
+        let b = &type_cache!(Huge, B).0;
+        assert!(!std::ptr::eq(a, b));
+        assert_eq!(b[0].load(Relaxed), 0);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| inline_cache!(Huge) as *const Huge as usize))
+            .collect();
+        let addresses: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(addresses.windows(2).all(|w| w[0] == w[1]));
+    }
+
+    #[test]
+    fn string_and_const_keys() {
+        fn a() -> &'static AtomicUsize {
+            type_cache!(AtomicUsize, "inline_cache.test.a")
+        }
+        fn a_again() -> &'static AtomicUsize {
+            type_cache!(_, "inline_cache.test.a")
+        }
+        fn b() -> &'static AtomicUsize {
+            type_cache!(AtomicUsize, "inline_cache.test.b")
+        }
+
+        assert!(std::ptr::eq(a(), a_again()));
+        assert!(!std::ptr::eq(a(), b()));
+        assert!(!std::ptr::eq(
+            type_cache!(AtomicUsize, "inline_cache.test.a\0"),
+            a()
+        ));
+
+        fn indexed<const I: usize>() -> &'static AtomicUsize {
+            type_cache!(AtomicUsize, const I)
+        }
+
+        assert!(std::ptr::eq(
+            indexed::<3>(),
+            type_cache!(AtomicUsize, const 3usize)
+        ));
+        assert!(!std::ptr::eq(indexed::<3>(), indexed::<4>()));
+        let padded: &'static AtomicUsize = type_cache!(padded AtomicUsize, "inline_cache.test.a");
+        assert!(!std::ptr::eq(padded, a()));
+    }
+
+    #[cfg(all(
+        not(any(feature = "force_fallback_impl", miri)),
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn debugger_names() {
+        use std::{collections::HashMap, sync::atomic::AtomicU8};
+
+        use object::{Object, ObjectSymbol};
+
+        struct Requests;
+        struct Errors;
+
+        fn counter<K>() -> &'static AtomicUsize {
+            type_cache!(AtomicUsize, K)
+        }
+
+        let anchor: &'static AtomicUsize =
+            inline_cache!(AtomicUsize, export = "inline_cache_debugger_names");
+        let untyped: &'static AtomicU8 = type_cache!(_, Errors);
+        let slots: [*const u8; 6] = [
+            (counter::<Requests>() as *const AtomicUsize).cast(),
+            (counter::<Errors>() as *const AtomicUsize).cast(),
+            (&type_cache!([AtomicUsize; 4], const 7)[0] as *const AtomicUsize).cast(),
+            (inline_cache!(AtomicUsize) as *const AtomicUsize).cast(),
+            (untyped as *const AtomicU8).cast(),
+            (crate::percpu_cache!(AtomicUsize) as *const PerCpu<AtomicUsize>).cast(),
+        ];
+
+        let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let exe = object::File::parse(&*exe).unwrap();
+        let symbols: HashMap<&str, u64> = exe
+            .symbols()
+            .filter_map(|symbol| Some((symbol.name().ok()?, symbol.address())))
+            .collect();
+        // Position independent executables are loaded at an offset, which is the same for all
+        // symbols.
+        let offset = anchor as *const _ as u64 - symbols["inline_cache_debugger_names"];
+        let slot_names: HashMap<usize, SlotKey> = slot_names().into_iter().collect();
+
+        // Every slot symbol, including those of the `_` forms and of the crate's own modules, has
+        // an alias and a name
+        let mut slot_count = 0;
+        for (name, &address) in &symbols {
+            let Some(symbol) = name.strip_suffix("_SLOT") else {
+                continue;
+            };
+            assert!(symbol.contains("slot_id"), "{name}");
+            assert_eq!(
+                symbols.get(&*format!("{symbol}.inline_cache")),
+                Some(&address)
+            );
+            assert!(
+                slot_names.contains_key(&((address + offset) as usize)),
+                "{name} has no name"
+            );
+            slot_count += 1;
+        }
+        assert_eq!(slot_count, slot_names.len());
+
+        let names: Vec<String> = slots
+            .iter()
+            .map(|&slot| slot_names[&(slot as usize)].to_string())
+            .collect();
+        // The names are built from the `type_name`s of `T` and `K` with any symbol mangling scheme,
+        // so every instantiation of generic code gets a name of its own
+        assert!(
+            names[0].contains("AtomicUsize") && names[0].contains("Requests"),
+            "{}",
+            names[0]
+        );
+        assert!(
+            names[1].contains("AtomicUsize") && names[1].contains("Errors"),
+            "{}",
+            names[1]
+        );
+        assert!(
+            names[2].contains("[core::sync::atomic::AtomicUsize; 4]"),
+            "{}",
+            names[2]
+        );
+        assert!(names[2].contains("ConstKey<7>"), "{}", names[2]);
+        assert!(
+            names[4].contains("AtomicU8") && names[4].contains("Errors"),
+            "{}",
+            names[4]
+        );
+        assert!(names[5].contains("PerCpu"), "{}", names[5]);
+        let mut unique = names.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), names.len());
+    }
+
+    #[test]
+    fn caller_cache() {
+        struct K;
+
+        #[track_caller]
+        fn count() -> usize {
+            caller_cache!(AtomicUsize, K).fetch_add(1, Relaxed)
+        }
+
+        let first = || count();
+        let second = || count();
+
+        assert_eq!(first(), 0);
+        assert_eq!(first(), 1);
+        assert_eq!(second(), 0);
+        assert_eq!(first(), 2);
+        assert_eq!(second(), 1);
+
+        let mut lines: Vec<_> = callers::<AtomicUsize, K>()
+            .into_iter()
+            .map(|(location, count)| (location.line(), count.load(Relaxed)))
+            .collect();
+        lines.sort();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].1, 3);
+        assert_eq!(lines[1].1, 2);
+        // Without a key, functions reached from the same caller have separate slots
+        #[track_caller]
+        fn hits() -> usize {
+            caller_cache!(AtomicUsize).fetch_add(1, Relaxed)
+        }
+        #[track_caller]
+        fn misses() -> usize {
+            caller_cache!(AtomicUsize).fetch_add(2, Relaxed)
+        }
+        #[track_caller]
+        fn both() -> (usize, usize) {
+            (hits(), misses())
+        }
+
+        for i in 0..3 {
+            assert_eq!(both(), (i, 2 * i));
+        }
+    }
+
+    #[test]
+    fn sections() {
+        fn hits<K>() -> &'static AtomicUsize {
+            inline_cache!(AtomicUsize, K, section = "inline_cache_test_slots")
+        }
+        fn padded() -> &'static AtomicUsize {
+            inline_cache!(padded AtomicUsize, section = "inline_cache_test_slots")
+        }
+        fn bss() -> &'static AtomicUsize {
+            inline_cache!(AtomicUsize, section = ".bss.inline_cache_test_slots")
+        }
+
+        // Slots can share their section with initialized data
+        #[unsafe(link_section = "inline_cache_test_slots")]
+        #[used]
+        static INITIALIZED: AtomicUsize = AtomicUsize::new(7);
+
+        struct A;
+        struct B;
+
+        assert_eq!(hits::<A>().fetch_add(1, Relaxed), 0);
+        assert_eq!(hits::<A>().fetch_add(1, Relaxed), 1);
+        assert_eq!(hits::<B>().fetch_add(1, Relaxed), 0);
+        assert_eq!(padded().fetch_add(1, Relaxed), 0);
+        assert_eq!(padded().fetch_add(1, Relaxed), 1);
+        assert_eq!(bss().fetch_add(1, Relaxed), 0);
+        assert_eq!(INITIALIZED.load(Relaxed), 7);
+        assert!(private::is_bss_section(".bss.inline_cache_test_slots"));
+        assert!(!private::is_bss_section(".data.bss"));
+
+        #[cfg(all(
+            not(any(feature = "force_fallback_impl", miri)),
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        {
+            unsafe extern "C" {
+                static __start_inline_cache_test_slots: [u8; 0];
+                static __stop_inline_cache_test_slots: [u8; 0];
+            }
+
+            let section = (&raw const __start_inline_cache_test_slots).cast::<u8>()
+                ..(&raw const __stop_inline_cache_test_slots).cast::<u8>();
+            for slot in [hits::<A>(), hits::<B>(), padded(), &INITIALIZED] {
+                assert!(section.contains(&(slot as *const AtomicUsize).cast()));
+            }
+        }
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn huge() {
@@ -0,0 +1,455 @@
+use std::{
+    fmt::Write,
+    panic::Location,
+    sync::atomic::{
+        AtomicBool, AtomicPtr, AtomicU64, AtomicUsize,
+        Ordering::{Acquire, Relaxed, Release},
+    },
+};
+
+use bytemuck::Zeroable;
+
+use crate::registry::Registry;
+
+/// Bucket upper bounds used by `histogram!` when no `buckets = ...` are given.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Maximal number of bucket upper bounds of a `histogram!`, excluding the implicit `+Inf` bucket.
+pub const MAX_BUCKETS: usize = 16;
+
+#[macro_export]
+macro_rules! counter {
+    ($name:literal $(, $T:ident)* $(,)?) => {
+        $crate::__metric!(Counter, $name, [$($T),*])
+    };
+}
+
+#[macro_export]
+macro_rules! gauge {
+    ($name:literal $(, $T:ident)* $(,)?) => {
+        $crate::__metric!(Gauge, $name, [$($T),*])
+    };
+}
+
+#[macro_export]
+macro_rules! histogram {
+    ($name:literal, buckets = $buckets:expr $(, $T:ident)* $(,)?) => {
+        $crate::__metric!(Histogram, $name, [$($T),*], $buckets)
+    };
+    ($name:literal $(, $T:ident)* $(,)?) => {
+        $crate::__metric!(Histogram, $name, [$($T),*], $crate::metrics::DEFAULT_BUCKETS)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metric {
+    ($Metric:ident, $name:literal, [$($T:ident),*] $(, $extra:expr)*) => {{
+        struct Callsite;
+        $crate::private::type_cache::<$crate::metrics::$Metric, (Callsite, ($($T,)*))>()
+            .registered(
+                $name,
+                || ::std::vec![$((::std::stringify!($T), ::std::any::type_name::<$T>())),*],
+                $($extra,)*
+            )
+    }};
+}
+
+/// A monotonically increasing counter, see `counter!`.
+pub struct Counter {
+    registered: AtomicBool,
+    value: AtomicU64,
+}
+
+unsafe impl Zeroable for Counter {}
+
+impl Counter {
+    #[doc(hidden)]
+    #[inline(always)]
+    #[track_caller]
+    pub fn registered(
+        &'static self,
+        name: &'static str,
+        labels: impl FnOnce() -> Vec<(&'static str, &'static str)>,
+    ) -> &'static Self {
+        register(
+            &self.registered,
+            name,
+            Location::caller(),
+            labels,
+            MetricRef::Counter(self),
+        );
+        self
+    }
+
+    #[inline]
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    #[inline]
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Relaxed);
+    }
+
+    #[inline]
+    pub fn get(&self) -> u64 {
+        self.value.load(Relaxed)
+    }
+}
+
+/// A value that can go up and down, see `gauge!`.
+pub struct Gauge {
+    registered: AtomicBool,
+    bits: AtomicU64,
+}
+
+unsafe impl Zeroable for Gauge {}
+
+impl Gauge {
+    #[doc(hidden)]
+    #[inline(always)]
+    #[track_caller]
+    pub fn registered(
+        &'static self,
+        name: &'static str,
+        labels: impl FnOnce() -> Vec<(&'static str, &'static str)>,
+    ) -> &'static Self {
+        register(
+            &self.registered,
+            name,
+            Location::caller(),
+            labels,
+            MetricRef::Gauge(self),
+        );
+        self
+    }
+
+    #[inline]
+    pub fn set(&self, value: f64) {
+        self.bits.store(value.to_bits(), Relaxed);
+    }
+
+    #[inline]
+    pub fn add(&self, value: f64) {
+        let _ = self.bits.fetch_update(Relaxed, Relaxed, |bits| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        });
+    }
+
+    #[inline]
+    pub fn sub(&self, value: f64) {
+        self.add(-value);
+    }
+
+    #[inline]
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    #[inline]
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    #[inline]
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Relaxed))
+    }
+}
+
+/// A histogram with fixed bucket upper bounds, see `histogram!`.
+pub struct Histogram {
+    registered: AtomicBool,
+    bounds: AtomicPtr<f64>,
+    bounds_len: AtomicUsize,
+    buckets: [AtomicU64; MAX_BUCKETS + 1],
+    count: AtomicU64,
+    sum_bits: AtomicU64,
+}
+
+unsafe impl Zeroable for Histogram {}
+
+impl Histogram {
+    #[doc(hidden)]
+    #[inline(always)]
+    #[track_caller]
+    pub fn registered(
+        &'static self,
+        name: &'static str,
+        labels: impl FnOnce() -> Vec<(&'static str, &'static str)>,
+        bounds: &'static [f64],
+    ) -> &'static Self {
+        if !self.registered.load(Relaxed) {
+            assert!(
+                bounds.len() <= MAX_BUCKETS,
+                "histogram {name:?} has more than {MAX_BUCKETS} buckets"
+            );
+            assert!(
+                bounds.is_sorted_by(|a, b| a < b),
+                "histogram {name:?} has unsorted buckets"
+            );
+            // Release pairs with the Acquire load of `bounds`, which must see the pointer stored
+            // before the length
+            self.bounds.store(bounds.as_ptr().cast_mut(), Relaxed);
+            self.bounds_len.store(bounds.len(), Release);
+            register(
+                &self.registered,
+                name,
+                Location::caller(),
+                labels,
+                MetricRef::Histogram(self),
+            );
+        }
+        self
+    }
+
+    fn bounds(&self) -> &'static [f64] {
+        let len = self.bounds_len.load(Acquire);
+        if len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.bounds.load(Relaxed), len) }
+    }
+
+    #[inline]
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds().partition_point(|&bound| bound < value);
+        self.buckets[bucket].fetch_add(1, Relaxed);
+        self.count.fetch_add(1, Relaxed);
+        let _ = self.sum_bits.fetch_update(Relaxed, Relaxed, |bits| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        });
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count.load(Relaxed)
+    }
+
+    #[inline]
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum_bits.load(Relaxed))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum MetricRef {
+    Counter(&'static Counter),
+    Gauge(&'static Gauge),
+    Histogram(&'static Histogram),
+}
+
+impl MetricRef {
+    fn kind(self) -> &'static str {
+        match self {
+            MetricRef::Counter(_) => "counter",
+            MetricRef::Gauge(_) => "gauge",
+            MetricRef::Histogram(_) => "histogram",
+        }
+    }
+}
+
+struct Registered {
+    name: &'static str,
+    location: &'static Location<'static>,
+    labels: Vec<(&'static str, &'static str)>,
+    metric: MetricRef,
+}
+
+static REGISTRY: Registry<Registered> = Registry::new();
+
+/// Registers the metric on its first use.
+///
+/// Panics if a metric of another kind was registered under the same name, which would render as
+/// conflicting `# TYPE` lines.
+#[inline(always)]
+#[track_caller]
+fn register(
+    registered: &AtomicBool,
+    name: &'static str,
+    location: &'static Location<'static>,
+    labels: impl FnOnce() -> Vec<(&'static str, &'static str)>,
+    metric: MetricRef,
+) {
+    let result = REGISTRY.try_register(registered, |entries| {
+        match entries.iter().find(|entry| entry.name == name) {
+            Some(other) if other.metric.kind() != metric.kind() => Err(other.metric.kind()),
+            _ => Ok(Registered {
+                name,
+                location,
+                labels: labels(),
+                metric,
+            }),
+        }
+    });
+    if let Err(other_kind) = result {
+        panic!(
+            "metric {name:?} is used as a {} but is already registered as a {other_kind}",
+            metric.kind()
+        );
+    }
+}
+
+/// Renders all metrics used so far in the Prometheus text exposition format.
+///
+/// Every sample is labeled with the `callsite` of the macro and with the `type_name` of each
+/// generic parameter passed to the macro.
+pub fn render_prometheus() -> String {
+    let registry = REGISTRY.lock();
+
+    let mut sorted: Vec<&Registered> = registry.iter().collect();
+    sorted.sort_by_key(|registered| registered.name);
+
+    let mut out = String::new();
+    let mut last_name = None;
+
+    for registered in sorted {
+        if last_name != Some(registered.name) {
+            last_name = Some(registered.name);
+            writeln!(
+                out,
+                "# TYPE {} {}",
+                registered.name,
+                registered.metric.kind()
+            )
+            .unwrap();
+        }
+
+        let mut labels = String::new();
+        write!(labels, "callsite=\"").unwrap();
+        escape_label_value(&mut labels, &registered.location.to_string());
+        labels.push('"');
+        for &(key, value) in &registered.labels {
+            write!(labels, ",{key}=\"").unwrap();
+            escape_label_value(&mut labels, value);
+            labels.push('"');
+        }
+
+        let name = registered.name;
+        match registered.metric {
+            MetricRef::Counter(counter) => {
+                writeln!(out, "{name}{{{labels}}} {}", counter.get()).unwrap();
+            }
+            MetricRef::Gauge(gauge) => {
+                writeln!(out, "{name}{{{labels}}} {}", FloatValue(gauge.get())).unwrap();
+            }
+            MetricRef::Histogram(histogram) => {
+                let mut cumulative = 0;
+                for (i, bucket) in histogram.buckets.iter().enumerate() {
+                    let Some(le) = histogram
+                        .bounds()
+                        .get(i)
+                        .copied()
+                        .or_else(|| (i == histogram.bounds().len()).then_some(f64::INFINITY))
+                    else {
+                        break;
+                    };
+                    cumulative += bucket.load(Relaxed);
+                    writeln!(
+                        out,
+                        "{name}_bucket{{{labels},le=\"{}\"}} {cumulative}",
+                        FloatValue(le)
+                    )
+                    .unwrap();
+                }
+                writeln!(
+                    out,
+                    "{name}_sum{{{labels}}} {}",
+                    FloatValue(histogram.sum())
+                )
+                .unwrap();
+                writeln!(out, "{name}_count{{{labels}}} {}", histogram.count()).unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+fn escape_label_value(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+struct FloatValue(f64);
+
+impl std::fmt::Display for FloatValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_nan() {
+            f.write_str("NaN")
+        } else if self.0.is_infinite() {
+            f.write_str(if self.0 > 0.0 { "+Inf" } else { "-Inf" })
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        fn handle<T>(size: f64) {
+            crate::counter!("test_requests_total", T).inc();
+            crate::gauge!("test_in_flight", T).set(size);
+            crate::histogram!("test_request_size", buckets = &[1.0, 10.0], T).observe(size);
+        }
+
+        handle::<u8>(0.5);
+        handle::<u8>(5.0);
+        handle::<Vec<u8>>(20.0);
+
+        let rendered = render_prometheus();
+
+        assert!(rendered.contains("# TYPE test_requests_total counter\n"));
+        assert!(rendered.contains("# TYPE test_in_flight gauge\n"));
+        assert!(rendered.contains("# TYPE test_request_size histogram\n"));
+
+        let sample = |line_start: &str| {
+            rendered
+                .lines()
+                .find(|line| line.starts_with(line_start) && !line.contains("Vec"))
+                .unwrap_or_else(|| panic!("missing {line_start} in {rendered}"))
+                .rsplit_once(' ')
+                .unwrap()
+                .1
+                .to_owned()
+        };
+
+        assert_eq!(sample("test_requests_total{"), "2");
+        assert_eq!(sample("test_in_flight{"), "5");
+        assert!(rendered.contains(",T=\"u8\",le=\"1\"} 1\n"));
+        assert!(rendered.contains(",T=\"u8\",le=\"10\"} 2\n"));
+        assert!(rendered.contains(",T=\"u8\",le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains(",T=\"alloc::vec::Vec<u8>\",le=\"10\"} 0\n"));
+        assert!(rendered.contains(",T=\"alloc::vec::Vec<u8>\",le=\"+Inf\"} 1\n"));
+        assert_eq!(sample("test_request_size_sum{"), "5.5");
+        assert_eq!(sample("test_request_size_count{"), "2");
+    }
+
+    #[test]
+    fn conflicting_kinds() {
+        crate::counter!("test_conflicting").inc();
+        let result = std::panic::catch_unwind(|| crate::gauge!("test_conflicting").set(1.0));
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(
+            *message,
+            "metric \"test_conflicting\" is used as a gauge but is already registered as a counter"
+        );
+
+        let rendered = render_prometheus();
+        assert_eq!(rendered.matches("# TYPE test_conflicting ").count(), 1);
+        assert!(rendered.contains("# TYPE test_conflicting counter\n"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    alloc::Layout,
+    cell::Cell,
+    ptr::null_mut,
+    sync::{
+        OnceLock,
+        atomic::{
+            AtomicPtr, AtomicUsize,
+            Ordering::{AcqRel, Acquire, Relaxed},
+        },
+    },
+};
+
+use bytemuck::Zeroable;
+
+use crate::CachePadded;
+
+#[macro_export]
+macro_rules! percpu_cache {
+    ($T:ty, $K:ty) => {{
+        struct InlineCache<K: ?Sized>(::std::marker::PhantomData<K>);
+        $crate::private::type_cache::<$crate::PerCpu<$T>, InlineCache<$K>>()
+    }};
+    ($T:ty) => {{
+        struct InlineCache;
+        $crate::private::type_cache::<$crate::PerCpu<$T>, InlineCache>()
+    }};
+}
+
+/// One cache line padded `T` per CPU, see `percpu_cache!`.
+///
+/// The shards are allocated on first use. `get` returns the shard of the CPU the current thread
+/// is running on, but as threads may migrate at any point, it can return any shard.
+pub struct PerCpu<T> {
+    shards: AtomicPtr<CachePadded<T>>,
+}
+
+unsafe impl<T> Zeroable for PerCpu<T> {}
+
+impl<T: Sync + Zeroable> PerCpu<T> {
+    /// Returns the shard of the current CPU.
+    #[inline]
+    pub fn get(&self) -> &T {
+        let shards = self.shards();
+        &shards[current_cpu() & (shards.len() - 1)]
+    }
+
+    /// Iterates over the shards of all CPUs.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.shards().iter().map(|shard| &shard.0)
+    }
+
+    #[inline]
+    fn shards(&self) -> &[CachePadded<T>] {
+        let mut shards = self.shards.load(Acquire);
+        if shards.is_null() {
+            shards = self.alloc_shards();
+        }
+        unsafe { std::slice::from_raw_parts(shards, shard_count()) }
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn alloc_shards(&self) -> *mut CachePadded<T> {
+        let Ok(layout) = Layout::array::<CachePadded<T>>(shard_count()) else {
+            std::process::abort();
+        };
+        let shards = unsafe { std::alloc::alloc_zeroed(layout) }.cast::<CachePadded<T>>();
+        if shards.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+
+        // Release publishes the zeroed shards to the `Acquire` load of `shards`, Acquire on failure
+        // makes the shards of the winning thread visible
+        match self
+            .shards
+            .compare_exchange(null_mut(), shards, AcqRel, Acquire)
+        {
+            Ok(_) => shards,
+            Err(existing) => {
+                unsafe { std::alloc::dealloc(shards.cast(), layout) };
+                existing
+            }
+        }
+    }
+}
+
+/// Number of shards, always a power of two so that CPU numbers can be masked.
+///
+/// Sized from the configured CPUs rather than `available_parallelism`, which is lowered by affinity
+/// masks and cgroup quotas while the CPU numbers returned by `current_cpu` still span all CPUs.
+fn shard_count() -> usize {
+    static SHARD_COUNT: OnceLock<usize> = OnceLock::new();
+    *SHARD_COUNT.get_or_init(|| {
+        configured_cpus()
+            .max(std::thread::available_parallelism().map_or(1, |count| count.get()))
+            .next_power_of_two()
+    })
+}
+
+fn configured_cpus() -> usize {
+    #[cfg(target_os = "linux")]
+    {
+        unsafe extern "C" {
+            fn sysconf(name: std::ffi::c_int) -> std::ffi::c_long;
+        }
+        // Same value in glibc and musl
+        const _SC_NPROCESSORS_CONF: std::ffi::c_int = 83;
+        let count = unsafe { sysconf(_SC_NPROCESSORS_CONF) };
+        if count > 0 {
+            return count as usize;
+        }
+    }
+
+    0
+}
+
+#[inline]
+fn current_cpu() -> usize {
+    #[cfg(target_os = "linux")]
+    {
+        unsafe extern "C" {
+            // Served from the vDSO or rseq area by the C library, so this doesn't enter the kernel
+            fn sched_getcpu() -> std::ffi::c_int;
+        }
+        let cpu = unsafe { sched_getcpu() };
+        if cpu >= 0 {
+            return cpu as usize;
+        }
+    }
+
+    thread_index()
+}
+
+#[inline]
+fn thread_index() -> usize {
+    static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static INDEX: Cell<usize> = const { Cell::new(usize::MAX) };
+    }
+    INDEX.with(|index| {
+        if index.get() == usize::MAX {
+            index.set(NEXT_INDEX.fetch_add(1, Relaxed));
+        }
+        index.get()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    #[test]
+    fn sharded_counter() {
+        fn counter() -> &'static super::PerCpu<AtomicUsize> {
+            crate::percpu_cache!(AtomicUsize)
+        }
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        counter().get().fetch_add(1, Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(
+            counter()
+                .iter()
+                .map(|shard| shard.load(Relaxed))
+                .sum::<usize>(),
+            4000
+        );
+        assert_eq!(counter().iter().count(), super::shard_count());
+        assert!(super::shard_count() >= super::configured_cpus());
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    any::type_name,
+    fmt::Write,
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+use bytemuck::Zeroable;
+
+use crate::{private::type_cache, registry::Registry};
+
+/// Counts the calls of the surrounding generic function, and the time spent in them, separately
+/// for every instantiation.
+///
+/// Place it at the top of the function body, the time is measured until the end of the enclosing
+/// block. Instantiations are reported by `profile_report` under the name of the function including
+/// its generic arguments, with the `type_name` of each generic parameter passed to the macro in
+/// addition. Time spent in recursive calls is counted once per active call.
+///
+/// Every invocation of the macro is profiled separately, so two invocations in the same function
+/// get two rows, told apart by the source location shown with them.
+///
+/// ```
+/// fn encode<B: AsRef<[u8]>>(buf: B) -> usize {
+///     inline_cache::instantiation_profile!(B);
+///     buf.as_ref().len()
+/// }
+/// encode(vec![0u8; 4]);
+/// encode(Box::<[u8]>::from([0u8; 4]));
+/// print!("{}", inline_cache::profile::profile_report());
+/// ```
+#[macro_export]
+macro_rules! instantiation_profile {
+    ($($T:ident),* $(,)?) => {
+        let __instantiation_profile = {
+            let key = $crate::__call_site_key!();
+            $crate::profile::Profile::start(&key, || {
+                ::std::vec![$((::std::stringify!($T), ::std::any::type_name::<$T>())),*]
+            })
+        };
+    };
+}
+
+/// The call count and cumulative time of an instantiation, see `instantiation_profile!`.
+pub struct Profile {
+    registered: AtomicBool,
+    calls: AtomicU64,
+    nanos: AtomicU64,
+}
+
+unsafe impl Zeroable for Profile {}
+
+impl Profile {
+    #[doc(hidden)]
+    #[inline(always)]
+    #[track_caller]
+    pub fn start<K>(
+        _key: &K,
+        params: impl FnOnce() -> Vec<(&'static str, &'static str)>,
+    ) -> ProfileGuard {
+        let profile = type_cache::<Profile, K>();
+        let location = Location::caller();
+        REGISTRY.register(&profile.registered, || {
+            let key = type_name::<K>();
+            Registered {
+                instantiation: key.strip_suffix("::{{closure}}").unwrap_or(key),
+                params: params(),
+                location,
+                profile,
+            }
+        });
+        profile.calls.fetch_add(1, Relaxed);
+        ProfileGuard {
+            profile,
+            start: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Relaxed)
+    }
+
+    #[inline]
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Relaxed))
+    }
+}
+
+/// Adds the time since `Profile::start` to the profile when dropped.
+pub struct ProfileGuard {
+    profile: &'static Profile,
+    start: Instant,
+}
+
+impl Drop for ProfileGuard {
+    #[inline]
+    fn drop(&mut self) {
+        let nanos = self
+            .start
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.profile.nanos.fetch_add(nanos, Relaxed);
+    }
+}
+
+struct Registered {
+    instantiation: &'static str,
+    params: Vec<(&'static str, &'static str)>,
+    location: &'static Location<'static>,
+    profile: &'static Profile,
+}
+
+static REGISTRY: Registry<Registered> = Registry::new();
+
+/// Renders a table of all instantiations profiled so far, sorted by descending total time.
+pub fn profile_report() -> String {
+    let registry = REGISTRY.lock();
+
+    let mut rows: Vec<(u64, Duration, &Registered)> = registry
+        .iter()
+        .map(|registered| {
+            let profile = registered.profile;
+            (profile.calls(), profile.total(), registered)
+        })
+        .collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:>12} {:>14} {:>12}  instantiation",
+        "calls", "total", "mean"
+    )
+    .unwrap();
+    for (calls, total, registered) in rows {
+        let mean = total / u32::try_from(calls.max(1)).unwrap_or(u32::MAX);
+        write!(
+            out,
+            "{calls:>12} {:>14} {:>12}  {}",
+            format!("{total:.3?}"),
+            format!("{mean:.3?}"),
+            registered.instantiation
+        )
+        .unwrap();
+        for (i, &(param, type_name)) in registered.params.iter().enumerate() {
+            let separator = if i == 0 { " [" } else { ", " };
+            write!(out, "{separator}{param}={type_name}").unwrap();
+        }
+        if !registered.params.is_empty() {
+            out.push(']');
+        }
+        writeln!(out, " at {}", registered.location).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{hint::black_box, thread::sleep};
+
+    use super::*;
+
+    #[test]
+    fn report() {
+        fn encode<B: AsRef<[u8]>>(buf: B, delay: Duration) -> usize {
+            crate::instantiation_profile!(B);
+            sleep(delay);
+            black_box(buf).as_ref().len()
+        }
+
+        for _ in 0..3 {
+            encode(vec![0u8; 4], Duration::ZERO);
+        }
+        encode(Box::<[u8]>::from([0u8; 4]), Duration::from_millis(20));
+
+        let report = profile_report();
+        let row = |instantiation: &str| {
+            report
+                .lines()
+                .position(|line| line.contains(instantiation))
+                .unwrap_or_else(|| panic!("missing {instantiation} in {report}"))
+        };
+        let boxed = row("encode<alloc::boxed::Box<[u8]>> [B=alloc::boxed::Box<[u8]>]");
+        let vec = row("encode<alloc::vec::Vec<u8>> [B=alloc::vec::Vec<u8>]");
+        assert!(boxed < vec, "{report}");
+        assert!(
+            report
+                .lines()
+                .nth(boxed)
+                .unwrap()
+                .trim_start()
+                .starts_with("1 ")
+        );
+        assert!(
+            report
+                .lines()
+                .nth(vec)
+                .unwrap()
+                .trim_start()
+                .starts_with("3 ")
+        );
+    }
+
+    #[test]
+    fn invocations() {
+        fn parse<T: Default>() -> T {
+            crate::instantiation_profile!(T);
+            {
+                crate::instantiation_profile!(T);
+            }
+            T::default()
+        }
+
+        parse::<u16>();
+        let report = profile_report();
+        let rows: Vec<&str> = report
+            .lines()
+            .filter(|row| row.contains("parse<u16> [T=u16] at "))
+            .collect();
+        assert_eq!(rows.len(), 2, "{report}");
+        assert_ne!(rows[0].split(" at ").nth(1), rows[1].split(" at ").nth(1));
+        assert!(rows[0].contains(file!()), "{report}");
+    }
+}
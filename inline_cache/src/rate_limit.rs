@@ -0,0 +1,244 @@
+use std::{
+    io,
+    panic::Location,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+    },
+    time::{Duration, Instant},
+};
+
+use bytemuck::Zeroable;
+
+use crate::{private::type_cache, registry::Registry};
+
+/// Runs the closure on the first call only, returning `None` on all further calls.
+///
+/// Every callsite gets an independent gate per instantiation of the surrounding generic items,
+/// also when the same function is passed at several callsites.
+#[macro_export]
+macro_rules! once {
+    ($f:expr $(,)?) => {{
+        let key = $crate::__call_site_key!();
+        $crate::rate_limit::once(&key, $f)
+    }};
+}
+
+/// Runs the closure on the first call and then on every `n`-th call, returning `None` otherwise.
+#[macro_export]
+macro_rules! every_n {
+    ($n:expr, $f:expr $(,)?) => {{
+        let key = $crate::__call_site_key!();
+        $crate::rate_limit::every_n(&key, $n, $f)
+    }};
+}
+
+/// Runs the closure at most once per `duration`, returning `None` for calls that are rate limited.
+#[macro_export]
+macro_rules! at_most_per {
+    ($duration:expr, $f:expr $(,)?) => {{
+        let key = $crate::__call_site_key!();
+        $crate::rate_limit::at_most_per(&key, $duration, $f)
+    }};
+}
+
+struct Gate {
+    registered: AtomicBool,
+    calls: AtomicU64,
+    executed: AtomicU64,
+    last_run: AtomicU64,
+}
+
+unsafe impl Zeroable for Gate {}
+
+impl Gate {
+    #[inline(always)]
+    #[track_caller]
+    fn get<K>(kind: &'static str) -> &'static Gate {
+        let gate = type_cache::<Gate, K>();
+        let location = Location::caller();
+        REGISTRY.register(&gate.registered, || Registered {
+            gate,
+            kind,
+            location,
+            instantiation: std::any::type_name::<K>(),
+        });
+        gate
+    }
+
+    #[inline(always)]
+    fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.executed.fetch_add(1, Relaxed);
+        f()
+    }
+}
+
+#[doc(hidden)]
+#[inline(always)]
+#[track_caller]
+pub fn once<K, F: FnOnce() -> R, R>(_key: &K, f: F) -> Option<R> {
+    let gate = Gate::get::<K>("once");
+    if gate.calls.fetch_add(1, Relaxed) == 0 {
+        Some(gate.run(f))
+    } else {
+        None
+    }
+}
+
+#[doc(hidden)]
+#[inline(always)]
+#[track_caller]
+pub fn every_n<K, F: FnOnce() -> R, R>(_key: &K, n: u64, f: F) -> Option<R> {
+    assert!(n > 0, "every_n! requires a positive n");
+    let gate = Gate::get::<K>("every_n");
+    if gate.calls.fetch_add(1, Relaxed).is_multiple_of(n) {
+        Some(gate.run(f))
+    } else {
+        None
+    }
+}
+
+#[doc(hidden)]
+#[inline(always)]
+#[track_caller]
+pub fn at_most_per<K, F: FnOnce() -> R, R>(_key: &K, duration: Duration, f: F) -> Option<R> {
+    let gate = Gate::get::<K>("at_most_per");
+    gate.calls.fetch_add(1, Relaxed);
+
+    // `last_run` stores the nanoseconds since `epoch()` plus one, so that zero means never
+    let now = epoch().elapsed().as_nanos() as u64 + 1;
+    let last_run = gate.last_run.load(Relaxed);
+    if last_run != 0 && now.saturating_sub(last_run) < duration.as_nanos() as u64 {
+        return None;
+    }
+    if gate
+        .last_run
+        .compare_exchange(last_run, now, Relaxed, Relaxed)
+        .is_err()
+    {
+        return None;
+    }
+    Some(gate.run(f))
+}
+
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+struct Registered {
+    gate: &'static Gate,
+    kind: &'static str,
+    location: &'static Location<'static>,
+    instantiation: &'static str,
+}
+
+static REGISTRY: Registry<Registered> = Registry::new();
+
+/// Number of calls a rate limited callsite suppressed so far.
+#[derive(Clone, Debug)]
+pub struct Suppressed {
+    /// The macro used, `"once"`, `"every_n"` or `"at_most_per"`.
+    pub kind: &'static str,
+    pub location: &'static Location<'static>,
+    /// The `type_name` of the callsite key, which names the generic instantiation it belongs to.
+    pub instantiation: &'static str,
+    pub count: u64,
+}
+
+/// Returns all rate limited callsites that suppressed at least one call.
+pub fn suppressed() -> Vec<Suppressed> {
+    REGISTRY
+        .lock()
+        .iter()
+        .filter_map(|registered| {
+            let calls = registered.gate.calls.load(Relaxed);
+            let executed = registered.gate.executed.load(Relaxed);
+            let count = calls.saturating_sub(executed);
+            (count > 0).then_some(Suppressed {
+                kind: registered.kind,
+                location: registered.location,
+                instantiation: registered.instantiation,
+                count,
+            })
+        })
+        .collect()
+}
+
+/// Writes one line per rate limited callsite that suppressed at least one call.
+pub fn report_suppressed(out: &mut impl io::Write) -> io::Result<()> {
+    for suppressed in suppressed() {
+        writeln!(
+            out,
+            "{}: {}! suppressed {} call(s) in {}",
+            suppressed.location, suppressed.kind, suppressed.count, suppressed.instantiation
+        )?;
+    }
+    Ok(())
+}
+
+/// Reports all suppressed calls to stderr when dropped, e.g. at the end of `main`.
+#[must_use]
+pub struct SuppressedReporter;
+
+impl Drop for SuppressedReporter {
+    fn drop(&mut self) {
+        let _ = report_suppressed(&mut io::stderr().lock());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_instantiation_gates() {
+        fn warn<T>() -> Option<&'static str> {
+            crate::once!(|| std::any::type_name::<T>())
+        }
+
+        assert_eq!(warn::<u8>(), Some("u8"));
+        assert_eq!(warn::<u8>(), None);
+        assert_eq!(warn::<u16>(), Some("u16"));
+        assert_eq!(warn::<u8>(), None);
+        assert_eq!(warn::<u16>(), None);
+        assert_eq!(warn::<u16>(), None);
+
+        fn message() -> &'static str {
+            "message"
+        }
+        assert_eq!(crate::once!(message), Some("message"));
+        assert_eq!(crate::once!(message), Some("message"));
+
+        let mut ran = vec![];
+        for i in 0..7 {
+            if let Some(i) = crate::every_n!(3, || i) {
+                ran.push(i);
+            }
+        }
+        assert_eq!(ran, [0, 3, 6]);
+
+        let mut ran = 0;
+        for _ in 0..3 {
+            crate::at_most_per!(Duration::from_secs(3600), || ran += 1);
+        }
+        assert_eq!(ran, 1);
+
+        let suppressed = suppressed();
+        let count = |kind: &str, instantiation: &str| {
+            suppressed
+                .iter()
+                .find(|s| s.kind == kind && s.instantiation.contains(instantiation))
+                .map(|s| s.count)
+        };
+        assert_eq!(count("once", "warn<u8>"), Some(2));
+        assert_eq!(count("once", "warn<u16>"), Some(2));
+        assert_eq!(count("every_n", "per_instantiation_gates"), Some(4));
+        assert_eq!(count("at_most_per", "per_instantiation_gates"), Some(2));
+
+        let mut report = vec![];
+        report_suppressed(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("once! suppressed 2 call(s) in "));
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    convert::Infallible,
+    process::abort,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{
+            AtomicBool,
+            Ordering::{Acquire, Relaxed, Release},
+        },
+    },
+};
+
+/// The slots of `metrics`, `rate_limit` or `profile` used so far, for reporting.
+///
+/// Every slot has a `registered` flag of its own, so that it is added once, on its first use.
+pub(crate) struct Registry<E>(Mutex<Vec<E>>);
+
+impl<E> Registry<E> {
+    pub(crate) const fn new() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+
+    /// Adds the entry returned by `entry` unless `registered` is set already.
+    #[inline(always)]
+    pub(crate) fn register(&self, registered: &AtomicBool, entry: impl FnOnce() -> E) {
+        let Ok(()) = self.try_register(registered, |_| Ok::<_, Infallible>(entry()));
+    }
+
+    /// Like `register`, but `entry` gets the entries registered so far and may reject the new
+    /// one. Its error is returned after the registry was unlocked.
+    #[inline(always)]
+    pub(crate) fn try_register<X>(
+        &self,
+        registered: &AtomicBool,
+        entry: impl FnOnce(&[E]) -> Result<E, X>,
+    ) -> Result<(), X> {
+        if registered.load(Acquire) {
+            return Ok(());
+        }
+        self.register_slow(registered, entry)
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn register_slow<X>(
+        &self,
+        registered: &AtomicBool,
+        entry: impl FnOnce(&[E]) -> Result<E, X>,
+    ) -> Result<(), X> {
+        let mut entries = self.lock();
+        if registered.load(Relaxed) {
+            return Ok(());
+        }
+        let entry = entry(&entries)?;
+        entries.push(entry);
+        registered.store(true, Release);
+        Ok(())
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Vec<E>> {
+        // Nothing panics while the registry is locked, so it can't be poisoned
+        let Ok(entries) = self.0.lock() else {
+            abort();
+        };
+        entries
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    any::type_name,
+    collections::BTreeMap,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    ptr::NonNull,
+    sync::atomic::{
+        AtomicBool, AtomicI8, AtomicI16, AtomicI32, AtomicI64, AtomicIsize, AtomicU8, AtomicU16,
+        AtomicU32, AtomicU64, AtomicUsize, Ordering::Relaxed,
+    },
+};
+
+use bytemuck::Zeroable;
+use inline_cache_abi::{SlotAction, SlotEntry, SlotOps, runtime_slots, slot_id};
+
+use crate::CachePadded;
+
+/// Slot types that are saved by `snapshot` and restored by `restore`.
+///
+/// This is the counterpart of `bytemuck::Pod` for slots, which are only accessible through shared
+/// references: plain old data that is mutable through the shared slot reference, like atomic
+/// integers and arrays of them.
+///
+/// A slot is registered if its type is known to implement this trait where `type_cache!` or
+/// `inline_cache!` is expanded, i.e. if it is a concrete type implementing it or a generic
+/// parameter bounded by it. Slots of the `_` forms, whose type is inferred, and of unbounded
+/// generic parameters are never registered.
+///
+/// Neither are the slots of `inline_cache!` with a `section`, nor the state kept by the crate's own
+/// modules, such as `metrics` counters and histograms, `rate_limit` gates, `percpu_cache!` shards
+/// and `instantiation_profile!` counts. A snapshot doesn't include them, and they keep their
+/// current value on `restore`.
+///
+/// # Safety
+///
+/// The saved bytes must describe the whole value, so that restoring them in another process is
+/// sound. The value must not contain pointers, references or handles of any kind. As slots can be
+/// accessed concurrently, implementations must only use atomic operations or other
+/// synchronization.
+pub unsafe trait SnapshotSlot: Sync + Zeroable {
+    /// Number of bytes written by `save`.
+    const SAVED_LEN: usize;
+
+    /// Appends exactly `SAVED_LEN` bytes describing the current value to `out`.
+    fn save(&self, out: &mut Vec<u8>);
+
+    /// Restores a value from exactly `SAVED_LEN` bytes written by `save`.
+    fn restore(&self, saved: &[u8]);
+}
+
+macro_rules! impl_snapshot_slot_for_atomics {
+    ($($Atomic:ty => $int:ty),* $(,)?) => {
+        $(
+            unsafe impl SnapshotSlot for $Atomic {
+                const SAVED_LEN: usize = size_of::<$int>();
+
+                fn save(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.load(Relaxed).to_le_bytes());
+                }
+
+                fn restore(&self, saved: &[u8]) {
+                    self.store(<$int>::from_le_bytes(saved.try_into().unwrap()), Relaxed);
+                }
+            }
+        )*
+    };
+}
+
+impl_snapshot_slot_for_atomics! {
+    AtomicU8 => u8,
+    AtomicU16 => u16,
+    AtomicU32 => u32,
+    AtomicU64 => u64,
+    AtomicUsize => usize,
+    AtomicI8 => i8,
+    AtomicI16 => i16,
+    AtomicI32 => i32,
+    AtomicI64 => i64,
+    AtomicIsize => isize,
+}
+
+unsafe impl SnapshotSlot for AtomicBool {
+    const SAVED_LEN: usize = 1;
+
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(self.load(Relaxed) as u8);
+    }
+
+    fn restore(&self, saved: &[u8]) {
+        self.store(saved[0] != 0, Relaxed);
+    }
+}
+
+unsafe impl<T: SnapshotSlot, const N: usize> SnapshotSlot for [T; N]
+where
+    [T; N]: Zeroable,
+{
+    const SAVED_LEN: usize = T::SAVED_LEN * N;
+
+    fn save(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn restore(&self, saved: &[u8]) {
+        for (item, saved) in self.iter().zip(saved.chunks_exact(T::SAVED_LEN.max(1))) {
+            item.restore(saved);
+        }
+    }
+}
+
+unsafe impl<T: SnapshotSlot> SnapshotSlot for CachePadded<T> {
+    const SAVED_LEN: usize = T::SAVED_LEN;
+
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+    }
+
+    fn restore(&self, saved: &[u8]) {
+        self.0.restore(saved);
+    }
+}
+
+/// Type erased access to a registered slot, referenced from the linker section registry.
+pub(crate) unsafe fn slot_ops<T: SnapshotSlot, K: ?Sized>(ptr: NonNull<u8>, action: SlotAction) {
+    let slot = unsafe { ptr.cast::<T>().as_ref() };
+    match action {
+        SlotAction::Describe(_) => describe_slot::<T, K>(ptr, action),
+        SlotAction::Save(out) => slot.save(out),
+        SlotAction::Restore(saved, ok) => {
+            *ok = saved.len() == T::SAVED_LEN;
+            if *ok {
+                slot.restore(saved);
+            }
+        }
+    }
+}
+
+/// Type erased description of a slot, referenced from the linker section of slot names, see
+/// `slot_names`. Only answers `SlotAction::Describe`.
+pub fn describe_slot<T, K: ?Sized>(_slot: NonNull<u8>, action: SlotAction) {
+    if let SlotAction::Describe(description) = action {
+        *description = Some((slot_id::<T, K>(), [type_name::<T>(), type_name::<K>()]));
+    }
+}
+
+fn registered_slots() -> Vec<(SlotKey, NonNull<u8>, SlotOps)> {
+    let mut entries = crate::private::linker_slots().to_vec();
+    entries.extend(runtime_slots());
+    describe_slots(entries)
+}
+
+fn describe_slots(entries: Vec<SlotEntry>) -> Vec<(SlotKey, NonNull<u8>, SlotOps)> {
+    let mut slots: Vec<_> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (slot, ops) = (entry.slot?, entry.ops?);
+            let mut description = None;
+            unsafe { ops(slot, SlotAction::Describe(&mut description)) };
+            let (id, [type_name, key_name]) = description?;
+
+            // `TypeId`s are opaque, but their hash is the same in every process of a build
+            let mut hasher = DefaultHasher::new();
+            id.hash(&mut hasher);
+            let key = SlotKey {
+                id: hasher.finish(),
+                type_name: type_name.to_owned(),
+                key_name: key_name.to_owned(),
+            };
+            Some((key, slot, ops))
+        })
+        .collect();
+    // The linker section registries may list a slot more than once when COMDAT deduplication isn't
+    // available
+    slots.sort_by_key(|(_, slot, _)| *slot);
+    slots.dedup_by_key(|(_, slot, _)| *slot);
+    slots
+}
+
+/// Returns the address and the key of every slot stored inline, sorted by address.
+///
+/// This names the slots independently of the symbol mangling scheme, e.g. to label the addresses
+/// in `perf c2c` reports. On the assembly based implementations for ELF targets, every slot stored
+/// inline gets an entry in a linker section, which describes it by the `type_name`s of its `T` and
+/// `K`. Its symbol also gets the local alias `{slot symbol}.inline_cache`, which demangles to
+/// `slot_id::<T, K>` with the v0 symbol mangling scheme, but only to `slot_id` and a hash with the
+/// default legacy scheme. Elsewhere slots have no symbols, and none are returned.
+pub fn slot_names() -> Vec<(usize, SlotKey)> {
+    describe_slots(crate::private::linker_slot_names().to_vec())
+        .into_iter()
+        .map(|(key, slot, _)| (slot.as_ptr() as usize, key))
+        .collect()
+}
+
+/// Identifies a slot across processes of the same build of a program.
+///
+/// Slots are matched up by `id`, which is derived from the `TypeId` of `T` and `K` and thus
+/// differs between slots whose types have the same name, e.g. the local key types of two
+/// `inline_cache!` calls in the same function. The type names are for diagnostics only.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SlotKey {
+    pub id: u64,
+    pub type_name: String,
+    pub key_name: String,
+}
+
+impl fmt::Display for SlotKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[T={}, K={}]", self.type_name, self.key_name)
+    }
+}
+
+/// The saved values of all registered slots, see `snapshot`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    slots: Vec<(SlotKey, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /// Returns the keys of all saved slots.
+    pub fn keys(&self) -> impl Iterator<Item = &SlotKey> {
+        self.slots.iter().map(|(key, _)| key)
+    }
+
+    /// Serializes the snapshot, e.g. to persist it for a later process.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn push_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+            out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+
+        let mut out = vec![];
+        out.extend_from_slice(&(self.slots.len() as u64).to_le_bytes());
+        for (key, saved) in &self.slots {
+            out.extend_from_slice(&key.id.to_le_bytes());
+            push_bytes(&mut out, key.type_name.as_bytes());
+            push_bytes(&mut out, key.key_name.as_bytes());
+            push_bytes(&mut out, saved);
+        }
+        out
+    }
+
+    /// Deserializes a snapshot produced by `to_bytes`, returns `None` if it is malformed.
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<Snapshot> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            let (taken, rest) = bytes.split_at_checked(len)?;
+            *bytes = rest;
+            Some(taken)
+        }
+        fn take_len(bytes: &mut &[u8]) -> Option<usize> {
+            usize::try_from(u64::from_le_bytes(take(bytes, 8)?.try_into().ok()?)).ok()
+        }
+        fn take_bytes<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+            let len = take_len(bytes)?;
+            take(bytes, len)
+        }
+
+        let count = take_len(&mut bytes)?;
+        let mut slots = vec![];
+        for _ in 0..count {
+            let key = SlotKey {
+                id: u64::from_le_bytes(take(&mut bytes, 8)?.try_into().ok()?),
+                type_name: String::from_utf8(take_bytes(&mut bytes)?.to_vec()).ok()?,
+                key_name: String::from_utf8(take_bytes(&mut bytes)?.to_vec()).ok()?,
+            };
+            slots.push((key, take_bytes(&mut bytes)?.to_vec()));
+        }
+        bytes.is_empty().then_some(Snapshot { slots })
+    }
+}
+
+/// Saves the values of all registered slots.
+///
+/// Slots are registered when their type is known to implement `SnapshotSlot`, see there for the
+/// slots that are excluded. On the assembly based implementations for ELF targets, all registered
+/// slots of the program are found via a linker section. Elsewhere only slots that were accessed at
+/// least once are found.
+pub fn snapshot() -> Snapshot {
+    let slots = registered_slots()
+        .into_iter()
+        .map(|(key, slot, ops)| {
+            let mut saved = vec![];
+            unsafe { ops(slot, SlotAction::Save(&mut saved)) };
+            (key, saved)
+        })
+        .collect();
+    Snapshot { slots }
+}
+
+/// Restores the values of all slots saved in a `Snapshot`.
+///
+/// The snapshot must have been taken by the same build of the program, slots of other builds are
+/// reported as missing. Slots that can be matched up are restored even when others can't. Slots
+/// that are registered but not part of the snapshot keep their current value.
+pub fn restore(snapshot: &Snapshot) -> Result<(), RestoreError> {
+    let mut live: BTreeMap<u64, Vec<(NonNull<u8>, SlotOps)>> = BTreeMap::new();
+    for (key, slot, ops) in registered_slots() {
+        live.entry(key.id).or_default().push((slot, ops));
+    }
+
+    let mut saved_count: BTreeMap<u64, usize> = BTreeMap::new();
+    for (key, _) in &snapshot.slots {
+        *saved_count.entry(key.id).or_default() += 1;
+    }
+
+    let mut error = RestoreError::default();
+
+    for (key, saved) in &snapshot.slots {
+        match live.get(&key.id).map(|slots| &slots[..]) {
+            None | Some([]) => error.missing.push(key.clone()),
+            Some(&[(slot, ops)]) if saved_count[&key.id] == 1 => {
+                let mut ok = false;
+                unsafe { ops(slot, SlotAction::Restore(saved, &mut ok)) };
+                if !ok {
+                    error.mismatched.push(key.clone());
+                }
+            }
+            // Slots with identical ids can't be told apart
+            Some(_) => error.mismatched.push(key.clone()),
+        }
+    }
+
+    error.mismatched.dedup();
+
+    if error.missing.is_empty() && error.mismatched.is_empty() {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// Slots of a `Snapshot` that `restore` could not restore.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RestoreError {
+    /// Saved slots that have no registered counterpart.
+    pub missing: Vec<SlotKey>,
+    /// Saved slots whose saved size doesn't match or that can't be uniquely identified.
+    pub mismatched: Vec<SlotKey>,
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not restore {} missing and {} mismatched slot(s)",
+            self.missing.len(),
+            self.mismatched.len()
+        )?;
+        for key in &self.missing {
+            write!(f, "\n  missing {key}")?;
+        }
+        for key in &self.mismatched {
+            write!(f, "\n  mismatched {key}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RestoreError {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU16, AtomicU32, AtomicUsize, Ordering::Relaxed};
+
+    use super::*;
+
+    #[test]
+    fn snapshot_restore() {
+        struct A;
+        struct B;
+        struct NotSaved;
+
+        fn a() -> &'static AtomicUsize {
+            crate::type_cache!(AtomicUsize, A)
+        }
+        fn b() -> &'static [AtomicU32; 4] {
+            crate::inline_cache!([AtomicU32; 4], B)
+        }
+        fn not_saved() -> &'static AtomicUsize {
+            crate::type_cache!(_, NotSaved)
+        }
+
+        a().store(5, Relaxed);
+        b()[3].store(7, Relaxed);
+        not_saved().store(1, Relaxed);
+
+        let saved = snapshot();
+        let saved_again = Snapshot::from_bytes(&saved.to_bytes()).unwrap();
+        assert_eq!(saved, saved_again);
+        assert!(!saved.keys().any(|key| key.key_name.contains("NotSaved")));
+
+        a().store(6, Relaxed);
+        b()[3].store(8, Relaxed);
+        not_saved().store(2, Relaxed);
+
+        restore(&saved_again).unwrap();
+        assert_eq!(a().load(Relaxed), 5);
+        assert_eq!(b()[3].load(Relaxed), 7);
+        assert_eq!(not_saved().load(Relaxed), 2);
+
+        let mut modified = saved_again.clone();
+        let (a_key, a_saved) = modified
+            .slots
+            .iter_mut()
+            .find(|(key, _)| key.key_name.ends_with("::A"))
+            .unwrap();
+        let a_key = a_key.clone();
+        a_saved.push(0);
+        modified.slots.push((
+            SlotKey {
+                id: 0,
+                type_name: "u8".into(),
+                key_name: "does::not::Exist".into(),
+            },
+            vec![0],
+        ));
+
+        let error = restore(&modified).unwrap_err();
+        assert!(error.mismatched.contains(&a_key));
+        assert!(
+            error
+                .missing
+                .iter()
+                .any(|key| key.key_name == "does::not::Exist")
+        );
+    }
+
+    #[test]
+    fn same_type_names() {
+        fn counters() -> [&'static AtomicU32; 2] {
+            [
+                crate::inline_cache!(AtomicU32),
+                crate::inline_cache!(AtomicU32),
+            ]
+        }
+        fn generic<T: SnapshotSlot>() -> &'static T {
+            struct Generic;
+            crate::type_cache!(T, Generic)
+        }
+
+        let [first, second] = counters();
+        first.store(1, Relaxed);
+        second.store(2, Relaxed);
+        generic::<AtomicU16>().store(3, Relaxed);
+
+        let saved = snapshot();
+        first.store(0, Relaxed);
+        second.store(0, Relaxed);
+        generic::<AtomicU16>().store(0, Relaxed);
+
+        restore(&saved).unwrap();
+        assert_eq!(first.load(Relaxed), 1);
+        assert_eq!(second.load(Relaxed), 2);
+        assert_eq!(generic::<AtomicU16>().load(Relaxed), 3);
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    alloc::Layout, any::TypeId, collections::HashMap, hash::BuildHasherDefault, process::abort,
+    ptr::NonNull, sync::RwLock,
+};
+
+use crate::{identity_hasher::IdentityHasher, register_runtime_slot, registry::SlotOps};
+
+struct Ptr(NonNull<u8>);
+
+unsafe impl Send for Ptr {}
+unsafe impl Sync for Ptr {}
+
+static SLOTS: RwLock<HashMap<TypeId, Ptr, BuildHasherDefault<IdentityHasher>>> = RwLock::new(
+    HashMap::with_hasher(<BuildHasherDefault<IdentityHasher>>::new()),
+);
+
+/// Returns the slot of the fallback implementations for a `slot_id`, if it was already allocated.
+#[inline]
+pub fn fallback_slot(id: TypeId) -> Option<NonNull<u8>> {
+    let Ok(slots) = SLOTS.read() else {
+        abort();
+    };
+    slots.get(&id).map(|found| found.0)
+}
+
+/// Returns the slot of the fallback implementations for a `slot_id`, allocating it if necessary.
+///
+/// A newly allocated slot is zero initialized and, given `ops`, registered for snapshots.
+#[inline(never)]
+#[cold]
+pub fn alloc_fallback_slot(id: TypeId, layout: Layout, ops: Option<SlotOps>) -> NonNull<u8> {
+    let Ok(mut slots) = SLOTS.write() else {
+        abort();
+    };
+
+    match slots.entry(id) {
+        std::collections::hash_map::Entry::Occupied(entry) => entry.get().0,
+        std::collections::hash_map::Entry::Vacant(entry) => {
+            let Some(ptr) = NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) }) else {
+                std::alloc::handle_alloc_error(layout);
+            };
+            entry.insert(Ptr(ptr));
+            if let Some(ops) = ops {
+                register_runtime_slot(ptr, ops);
+            }
+
+            ptr
+        }
+    }
+}
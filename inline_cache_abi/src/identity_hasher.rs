@@ -1,3 +1,5 @@
+/// Hasher for keys that are already uniformly distributed, like `TypeId`s. Shared with
+/// `inline_cache`, but not part of the slot ABI.
 #[derive(Default)]
 pub struct IdentityHasher {
     state: u64,
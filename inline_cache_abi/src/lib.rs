@@ -0,0 +1,171 @@
+//! The slot ABI shared by all versions of `inline_cache`.
+//!
+//! Everything that decides which slot a `type_cache!(T, K)` refers to and where its value lives is
+//! defined here instead of in `inline_cache` itself. Semver incompatible versions of
+//! `inline_cache` in the same dependency graph thus share their slots, as long as they depend on
+//! the same major version of this crate.
+//!
+//! The major version of this crate is the layout compatibility tag of the slot ABI, which
+//! consists of
+//! - the `slot_id::<T, K>` function, whose symbol name is the prefix of the `_SLOT` and `_REG`
+//!   symbols of the assembly based implementations, and whose address and returned `TypeId` key
+//!   the fallback implementations,
+//! - `HEAP_THRESHOLD` and the `HeapSlot` key, which decide where the value of a slot lives,
+//! - the `StrKey` and `ConstKey` types for keys given as a string or an integer, where string keyed
+//!   slots always live in the fallback slot map, so that they are shared between crates,
+//! - the fallback slot map, see `fallback_slot`,
+//! - the `inline_cache_slots` linker section holding one `SlotEntry` per registered slot and the
+//!   runtime slot registry, see `register_runtime_slot`,
+//! - the `inline_cache_slot_names` linker section holding one `SlotEntry` per slot stored inline,
+//!   whose ops only answer `SlotAction::Describe`,
+//! - `GENERIC_SINGLETON_TABLE`.
+//!
+//! Any change to these requires a new major version. Linking two major versions into the same
+//! program fails with a duplicate definition of the `inline_cache_slot_abi` symbol, instead of
+//! silently giving the same `(T, K)` two slots.
+use std::{any::TypeId, marker::PhantomData, ptr::null_mut, sync::atomic::AtomicPtr};
+
+pub use fallback::{alloc_fallback_slot, fallback_slot};
+#[doc(hidden)]
+pub use identity_hasher::IdentityHasher;
+pub use registry::{SlotAction, SlotEntry, SlotOps, register_runtime_slot, runtime_slots};
+
+mod fallback;
+mod identity_hasher;
+mod registry;
+
+/// Slots of types larger than this many bytes are allocated on the heap on first use, so that only
+/// a pointer to them is stored inline.
+pub const HEAP_THRESHOLD: usize = 64 * 1024;
+
+/// Key of the inline `AtomicPtr` slot pointing to the heap allocated value of a huge slot with the
+/// key `K`.
+pub struct HeapSlot<K: ?Sized>(PhantomData<K>);
+
+/// Key of the inline `AtomicPtr` slot caching the address of a slot that lives in the fallback
+/// slot map, used for string keys.
+pub struct SharedSlot<K: ?Sized>(PhantomData<K>);
+
+/// Longest string key supported by `StrKey`.
+pub const MAX_STR_KEY_LEN: usize = 64;
+
+/// Key given as a string of `LEN` bytes, stored little endian in the chunks `C0` to `C3`.
+///
+/// Stable Rust doesn't allow `&'static str` const parameters, so the macros build this type using
+/// `str_key_chunk`.
+pub struct StrKey<const LEN: usize, const C0: u128, const C1: u128, const C2: u128, const C3: u128>;
+
+/// Returns the `chunk`-th chunk of a `StrKey` for `key`.
+pub const fn str_key_chunk(key: &str, chunk: usize) -> u128 {
+    let bytes = key.as_bytes();
+    assert!(
+        bytes.len() <= MAX_STR_KEY_LEN,
+        "string slot keys are limited to 64 bytes"
+    );
+
+    let mut value = 0;
+    let mut i = 0;
+    while i < 16 && chunk * 16 + i < bytes.len() {
+        value |= (bytes[chunk * 16 + i] as u128) << (8 * i);
+        i += 1;
+    }
+    value
+}
+
+/// Key given as an integer.
+pub struct ConstKey<const N: usize>;
+
+/// Points to the global table of `generic_singleton`, shared by all of its versions.
+///
+/// Slots of the same `(T, K)` referenced from different crates only share a symbol when the
+/// referencing code is instantiated in the same crate, so state shared between crates that aren't
+/// generic over it lives here. The table contains a layout tag of its own, checked by every
+/// version of `generic_singleton`.
+pub static GENERIC_SINGLETON_TABLE: AtomicPtr<()> = AtomicPtr::new(null_mut());
+
+trait PhantomAny {
+    fn inner_type_id(&self) -> std::any::TypeId
+    where
+        Self: 'static;
+}
+
+impl<T: ?Sized> PhantomAny for PhantomData<T> {
+    #[inline(always)]
+    fn inner_type_id(&self) -> std::any::TypeId
+    where
+        Self: 'static,
+    {
+        std::any::TypeId::of::<Self>()
+    }
+}
+
+#[inline(always)]
+fn erased_type_id<T>() -> TypeId {
+    let phantom: PhantomData<T> = PhantomData;
+    let dyn_phantom: &dyn PhantomAny = &phantom;
+    let dyn_static_phantom: &(dyn PhantomAny + 'static) = unsafe {
+        // LOCAL-SHIM (not committed)
+        &*std::mem::transmute::<*const (dyn PhantomAny + '_), *const (dyn PhantomAny + 'static)>(
+            dyn_phantom,
+        )
+    };
+    PhantomAny::inner_type_id(dyn_static_phantom)
+}
+
+struct SlotId<T, K: ?Sized>(PhantomData<T>, PhantomData<K>);
+
+/// Identifies the slot of `type_cache!(T, K)`.
+pub fn slot_id<T, K: ?Sized>() -> TypeId {
+    // Makes every program using a slot link in the `inline_cache_slot_abi` symbol
+    #[cfg(not(any(miri, target_family = "wasm")))]
+    std::hint::black_box(&raw const SLOT_ABI);
+
+    erased_type_id::<SlotId<T, K>>()
+}
+
+// Defines the program wide `inline_cache_slot_abi` symbol in the same object file as a symbol
+// specific to this major version, which is referenced by `slot_id`. Unlike separate statics, this
+// makes sure the linker sees both definitions when two major versions are used.
+#[cfg(not(any(miri, target_family = "wasm")))]
+macro_rules! slot_abi {
+    ($prefix:literal) => {
+        core::arch::global_asm!(
+            ".data",
+            ".p2align 3",
+            concat!(".globl ", $prefix, "inline_cache_slot_abi"),
+            concat!(".set ", $prefix, "inline_cache_slot_abi, ."),
+            concat!(
+                ".globl ",
+                $prefix,
+                "inline_cache_slot_abi_v",
+                env!("CARGO_PKG_VERSION_MAJOR")
+            ),
+            concat!(
+                ".set ",
+                $prefix,
+                "inline_cache_slot_abi_v",
+                env!("CARGO_PKG_VERSION_MAJOR"),
+                ", ."
+            ),
+            concat!(".quad ", env!("CARGO_PKG_VERSION_MAJOR")),
+            ".text",
+        );
+
+        unsafe extern "C" {
+            #[link_name = concat!("inline_cache_slot_abi_v", env!("CARGO_PKG_VERSION_MAJOR"))]
+            static SLOT_ABI: u64;
+        }
+    };
+}
+
+#[cfg(all(
+    not(any(miri, target_family = "wasm")),
+    any(target_vendor = "apple", all(windows, target_arch = "x86"))
+))]
+slot_abi!("_");
+
+#[cfg(all(
+    not(any(miri, target_family = "wasm")),
+    not(any(target_vendor = "apple", all(windows, target_arch = "x86")))
+))]
+slot_abi!("");
@@ -0,0 +1,49 @@
+use std::{any::TypeId, process::abort, ptr::NonNull, sync::Mutex};
+
+/// Requests passed to the `SlotOps` of a registered slot.
+pub enum SlotAction<'a> {
+    /// Stores the `slot_id` and the type names of the slot's `T` and `K`.
+    Describe(&'a mut Option<(TypeId, [&'static str; 2])>),
+    /// Appends the saved value of the slot.
+    Save(&'a mut Vec<u8>),
+    /// Restores a saved value of the slot, storing whether it had the expected size.
+    Restore(&'a [u8], &'a mut bool),
+}
+
+/// Type erased access to a registered slot.
+pub type SlotOps = unsafe fn(NonNull<u8>, SlotAction<'_>);
+
+/// An entry of a slot registry.
+///
+/// The `inline_cache_slots` and `inline_cache_slot_names` linker sections are arrays of these,
+/// where entries with a `None` slot are skipped.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SlotEntry {
+    pub slot: Option<NonNull<u8>>,
+    pub ops: Option<SlotOps>,
+}
+
+unsafe impl Send for SlotEntry {}
+
+/// Slots that are allocated at runtime by the fallback implementations or for heap backed types.
+static RUNTIME_SLOTS: Mutex<Vec<SlotEntry>> = Mutex::new(Vec::new());
+
+/// Registers a slot allocated at runtime.
+pub fn register_runtime_slot(slot: NonNull<u8>, ops: SlotOps) {
+    let Ok(mut runtime_slots) = RUNTIME_SLOTS.lock() else {
+        abort();
+    };
+    runtime_slots.push(SlotEntry {
+        slot: Some(slot),
+        ops: Some(ops),
+    });
+}
+
+/// Returns all slots registered with `register_runtime_slot`.
+pub fn runtime_slots() -> Vec<SlotEntry> {
+    let Ok(runtime_slots) = RUNTIME_SLOTS.lock() else {
+        abort();
+    };
+    runtime_slots.clone()
+}
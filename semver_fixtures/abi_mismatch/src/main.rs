@@ -0,0 +1,5 @@
+fn main() {
+    let a = inline_cache_abi_1::slot_id::<u8, ()>();
+    let b = inline_cache_abi_2::slot_id::<u8, ()>();
+    println!("{a:?} {b:?}");
+}
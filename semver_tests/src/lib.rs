@@ -0,0 +1,11 @@
+//! Tests using semver incompatible versions of `inline_cache` and `generic_singleton` side by side.
+//!
+//! The `0.2` versions in `fixtures` are built from the same sources as the `0.1` versions of the
+//! workspace, so they use the same slot ABI.
+
+use std::sync::atomic::AtomicUsize;
+
+/// A slot shared by name with the tests, which are a separate crate.
+pub fn named_slot() -> &'static AtomicUsize {
+    inline_cache_0_2::type_cache!(AtomicUsize, "semver_tests.named")
+}
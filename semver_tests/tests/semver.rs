@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+#[test]
+fn shared_slots() {
+    struct K;
+
+    let a: &'static AtomicUsize = inline_cache_0_1::type_cache!(AtomicUsize, K);
+    let b: &'static AtomicUsize = inline_cache_0_2::type_cache!(AtomicUsize, K);
+    assert!(std::ptr::eq(a, b));
+
+    a.fetch_add(1, Relaxed);
+    assert_eq!(b.load(Relaxed), 1);
+
+    macro_rules! registered {
+        ($snapshot:expr) => {
+            $snapshot
+                .keys()
+                .filter(|key| key.key_name.ends_with("shared_slots::K"))
+                .count()
+        };
+    }
+    assert_eq!(registered!(inline_cache_0_1::snapshot()), 1);
+    assert_eq!(registered!(inline_cache_0_2::snapshot()), 1);
+}
+
+#[test]
+fn shared_named_slots() {
+    let a: &'static AtomicUsize = inline_cache_0_1::type_cache!(AtomicUsize, "semver_tests.named");
+    assert!(std::ptr::eq(a, semver_tests::named_slot()));
+}
+
+#[test]
+fn shared_heap_slots() {
+    struct K;
+
+    struct Huge([AtomicUsize; 2 * inline_cache_0_1::HEAP_THRESHOLD / size_of::<AtomicUsize>()]);
+    unsafe impl bytemuck::Zeroable for Huge {}
+
+    let a: &'static Huge = inline_cache_0_1::type_cache!(Huge, K);
+    let b: &'static Huge = inline_cache_0_2::type_cache!(Huge, K);
+    assert!(std::ptr::eq(a, b));
+
+    a.0[0].fetch_add(1, Relaxed);
+    assert_eq!(b.0[0].load(Relaxed), 1);
+}
+
+#[test]
+fn shared_singletons() {
+    #[derive(Default)]
+    struct A(#[allow(dead_code)] AtomicUsize);
+    struct B(usize);
+
+    let a = generic_singleton_0_1::singleton::<A>();
+    assert!(std::ptr::eq(a, generic_singleton_0_2::singleton::<A>()));
+
+    assert_eq!(generic_singleton_0_2::singleton_with(|| B(1)).0, 1);
+    assert_eq!(generic_singleton_0_1::singleton_with(|| B(2)).0, 1);
+}
+
+#[test]
+#[cfg_attr(any(miri, target_family = "wasm"), ignore)]
+fn incompatible_slot_abis_fail_to_link() {
+    let fixture = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../semver_fixtures/abi_mismatch/Cargo.toml"
+    );
+    let output = std::process::Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "--offline",
+            "--manifest-path",
+            fixture,
+            "--target-dir",
+        ])
+        .arg(concat!(env!("CARGO_TARGET_TMPDIR"), "/abi_mismatch"))
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(
+        stderr.lines().any(|line| {
+            line.contains("inline_cache_slot_abi")
+                && ["multiple definition", "duplicate symbol", "LNK2005"]
+                    .iter()
+                    .any(|error| line.contains(error))
+        }),
+        "{stderr}"
+    );
+}